@@ -0,0 +1,308 @@
+//! Password hashing, session tokens, and the request guard which identifies the calling user.
+//!
+//! A successful registration or login creates a `session` record and hands its token to the
+//! client, which then authenticates its requests with an `Authorization: Bearer <token>` header.
+//! Only a SHA-256 digest of each token is stored, so a leaked database does not leak sessions.
+//!
+//! Authorization is layered on top of authentication: routes which mutate data under
+//! "/api/user/<id>/..." take an [`AuthedUser`] and call [`AuthedUser::ensure_is`] with `<id>`
+//! before doing anything else, and administrative routes take an [`AdminUser`].
+
+use crate::{db::record_id, err::LogMapErr, State};
+
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+
+use color_eyre::eyre::{eyre, Result};
+
+use rocket::{
+    http::Status,
+    outcome::try_outcome,
+    request::{FromRequest, Outcome, Request},
+};
+
+use sha2::{Digest, Sha256};
+
+use surrealdb::{engine::any::Any, Surreal};
+
+use tracing::{debug, info, instrument};
+
+use ulid::Ulid;
+
+/// How long a session token remains valid after it is issued.
+const SESSION_DURATION: &str = "30d";
+
+/// Hash a password with Argon2id and a random salt into a PHC string, which is what gets stored
+/// in the `password_hash` field of a `user`.
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| eyre!("failed to hash password: {err}"))
+}
+
+/// Check a password against a PHC string produced by [`hash_password`].
+pub fn verify_password(password: &str, password_hash: &str) -> Result<bool> {
+    let password_hash = PasswordHash::new(password_hash)
+        .map_err(|err| eyre!("failed to parse stored password hash: {err}"))?;
+
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &password_hash)
+        .is_ok())
+}
+
+/// Spend as long as [`verify_password`] would on a user who does not exist, so that the response
+/// time of a failed login does not reveal whether the username is taken.
+pub fn verify_missing_password(password: &str) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let dummy_hash = DUMMY_HASH.get_or_init(|| {
+        hash_password("not the password of any user").expect("failed to hash dummy password")
+    });
+
+    let _ = verify_password(password, dummy_hash);
+}
+
+/// Lowercase hex encoding of the SHA-256 digest of a session token.
+fn token_digest(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Create a new session for the given user, returning the token which identifies it.
+#[instrument(skip(db))]
+pub async fn create_session(db: &Surreal<Any>, user: Ulid) -> Result<String> {
+    let mut bytes = [0; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    let token: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    let query = format!(
+        "CREATE session:ulid() SET
+            user = $user,
+            token_digest = $token_digest,
+            expires = time::now() + {SESSION_DURATION}"
+    );
+
+    db.query(query)
+        .bind(("user", record_id("user", user)))
+        .bind(("token_digest", token_digest(&token)))
+        .await?
+        .check()?;
+
+    Ok(token)
+}
+
+/// Make the user with the given username an admin, so that they may use [`AdminUser`] routes.
+#[instrument(skip(db))]
+pub async fn grant_admin(db: &Surreal<Any>, username: String) -> Result<()> {
+    let granted: Vec<Ulid> = db
+        .query(
+            "UPDATE user SET admin = true WHERE username == $username RETURN VALUE record::id(id)",
+        )
+        .bind(("username", username.clone()))
+        .await?
+        .take(0)?;
+
+    if granted.is_empty() {
+        return Err(eyre!("there is no user with the username {username:?}"));
+    }
+
+    info!("granted admin to {username:?}");
+
+    Ok(())
+}
+
+/// Request guard for routes that require the caller to be logged in. Fails with 401 if the request
+/// has no `Authorization: Bearer <token>` header or the token does not belong to a live session.
+#[derive(Debug, Clone, Copy)]
+pub struct AuthedUser {
+    /// ID of the calling user.
+    pub id: Ulid,
+
+    /// ID of the session through which the user is calling.
+    pub session: Ulid,
+}
+
+impl AuthedUser {
+    /// Succeed only if the caller is the user with the given ID; otherwise fail with 403. This is
+    /// the policy of every route which mutates data belonging to a particular user.
+    pub fn ensure_is(&self, user: Ulid) -> Result<(), Status> {
+        if self.id == user {
+            Ok(())
+        } else {
+            debug!("user {} may not act as user {user}", self.id);
+            Err(Status::Forbidden)
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthedUser {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        #[derive(serde::Deserialize)]
+        struct Session {
+            id: Ulid,
+            user: Ulid,
+        }
+
+        let Some(token) = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        else {
+            return Outcome::Error((Status::Unauthorized, ()));
+        };
+
+        let state = try_outcome!(req.guard::<&rocket::State<State<Any>>>().await);
+
+        let session = async {
+            state
+                .db
+                .query(
+                    "SELECT record::id(id) AS id, record::id(user) AS user FROM session
+                    WHERE token_digest == $token_digest AND expires > time::now()",
+                )
+                .bind(("token_digest", token_digest(token)))
+                .await
+                .log_map_err(|_| Status::InternalServerError)?
+                .take::<Option<Session>>(0)
+                .log_map_err(|_| Status::InternalServerError)
+        };
+
+        match session.await {
+            Ok(Some(Session { id, user })) => Outcome::Success(AuthedUser {
+                id: user,
+                session: id,
+            }),
+            Ok(None) => {
+                debug!("rejected unknown or expired session token");
+                Outcome::Error((Status::Unauthorized, ()))
+            }
+            Err(status) => Outcome::Error((status, ())),
+        }
+    }
+}
+
+/// Request guard for administrative routes. Fails with 401 under the same conditions as
+/// [`AuthedUser`], and with 403 if the caller's `user` record does not have `admin = true`.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminUser(pub AuthedUser);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let authed = try_outcome!(req.guard::<AuthedUser>().await);
+
+        let state = try_outcome!(req.guard::<&rocket::State<State<Any>>>().await);
+
+        let is_admin = async {
+            state
+                .db
+                .query("RETURN $user.admin == true")
+                .bind(("user", record_id("user", authed.id)))
+                .await
+                .log_map_err(|_| Status::InternalServerError)?
+                .take::<Option<bool>>(0)
+                .log_map_err(|_| Status::InternalServerError)
+        };
+
+        match is_admin.await {
+            Ok(Some(true)) => Outcome::Success(AdminUser(authed)),
+            Ok(_) => {
+                debug!("user {} is not an admin", authed.id);
+                Outcome::Error((Status::Forbidden, ()))
+            }
+            Err(status) => Outcome::Error((status, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::record_id,
+        testing::{TestServer, TestUser},
+    };
+
+    use rocket::http::{Header, Status};
+
+    /// A server with a single logged-in user.
+    async fn setup() -> (TestServer, TestUser) {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let major = server.major("Mathematics").await;
+        let user = server
+            .user("jacobhenn", ("Jacob", "Henn"), university, major)
+            .await;
+
+        (server, user)
+    }
+
+    #[rocket::async_test]
+    async fn accepts_live_session() {
+        let (server, user) = setup().await;
+
+        let response = server
+            .client
+            .get("/api/me")
+            .header(user.auth())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_missing_or_unknown_token() {
+        let (server, _) = setup().await;
+
+        let response = server.client.get("/api/me").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = server
+            .client
+            .get("/api/me")
+            .header(Header::new("Authorization", "Bearer 0123456789abcdef"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_expired_session() {
+        let (server, user) = setup().await;
+
+        server
+            .db()
+            .query("UPDATE session SET expires = time::now() - 1s WHERE user == $user")
+            .bind(("user", record_id("user", user.id)))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let response = server
+            .client
+            .get("/api/me")
+            .header(user.auth())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
@@ -33,6 +33,20 @@ enum Subcommand {
 
     /// Clear the database and re-insert the test data in `surql/test_data.surql` (also re-sets up tables)
     ResetData,
+
+    /// Recompute all users' stats and assignment statuses from their activity, reporting any users
+    /// whose stored values differed
+    RepairStats,
+
+    /// Make the user with the given username an admin
+    GrantAdmin { username: String },
+
+    /// Delete media files in 'test_media' which are no longer referenced by the database
+    SweepMedia {
+        /// Only report which media would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -85,9 +99,10 @@ fn main() -> eyre::Result<()> {
     color_eyre::install()?;
 
     let args = Args::parse();
-    let config: Config = toml::from_str(&fs::read_to_string("unistellar-helper.toml").wrap_err(
-        format!("failed to read config file at 'unistellar-helper.toml"),
-    )?)
+    let config: Config = toml::from_str(
+        &fs::read_to_string("unistellar-helper.toml")
+            .wrap_err("failed to read config file at 'unistellar-helper.toml'")?,
+    )
     .wrap_err("failed to parse config")?;
 
     match args.subcommand {
@@ -121,6 +136,25 @@ fn main() -> eyre::Result<()> {
                 import_file(&config, file_path)?;
             }
         }
+        Subcommand::RepairStats => run_cmd!(
+            "cargo",
+            ["run", "--"],
+            ["--db-addr", &config.db_addr, "--media-dir", "test_media"],
+            ["repair-stats"]
+        )?,
+        Subcommand::GrantAdmin { username } => run_cmd!(
+            "cargo",
+            ["run", "--"],
+            ["--db-addr", &config.db_addr, "--media-dir", "test_media"],
+            ["grant-admin", &username]
+        )?,
+        Subcommand::SweepMedia { dry_run } => run_cmd!(
+            "cargo",
+            ["run", "--"],
+            ["--db-addr", &config.db_addr, "--media-dir", "test_media"],
+            ["sweep-media"],
+            dry_run.then_some("--dry-run")
+        )?,
     }
 
     Ok(())
@@ -0,0 +1,149 @@
+//! Normalization and de-duplication of user-input courses and assignments.
+//!
+//! Since courses are input by users rather than populated from an official source, the same
+//! course will inevitably be input several times with slightly different spellings (e.g.
+//! "mat4170" and "MAT 4170"). The helpers here put course codes and names into a canonical form
+//! and detect courses which are probably duplicates of each other.
+
+use triple_accel::levenshtein::rdamerau;
+
+/// Put a course code into the canonical form of an uppercase subject prefix, a single space, and
+/// the course number (e.g. "mat4170", "MAT-4170", and "Mat  4170" all become "MAT 4170"). Codes
+/// which do not start with a subject prefix followed by a number are just uppercased with their
+/// whitespace collapsed. Returns `None` if the code is blank.
+pub fn normalize_course_code(code: &str) -> Option<String> {
+    let compact: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '_')
+        .flat_map(char::to_uppercase)
+        .collect();
+
+    if compact.is_empty() {
+        return None;
+    }
+
+    let split = compact
+        .find(|c: char| !c.is_alphabetic())
+        .unwrap_or(compact.len());
+
+    let (subject, number) = compact.split_at(split);
+
+    if subject.is_empty() || !number.starts_with(|c: char| c.is_ascii_digit()) {
+        return Some(normalize_name(&code.to_uppercase()));
+    }
+
+    Some(format!("{subject} {number}"))
+}
+
+/// Trim a course or assignment name and collapse runs of whitespace into single spaces.
+pub fn normalize_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Whether two normalized strings are equal up to case and a small number of typos. The number of
+/// typos allowed grows with the length of the strings, so that short codes must be nearly
+/// identical.
+fn nearly_equal(a: &str, b: &str) -> bool {
+    let a = a.to_lowercase();
+    let b = b.to_lowercase();
+
+    let max_typos = (a.len().min(b.len()) / 8).max(1) as u32;
+
+    rdamerau(a.as_bytes(), b.as_bytes()) <= max_typos
+}
+
+/// Whether a new course with the given normalized code and name is probably a duplicate of an
+/// existing course at the same university with the given normalized code and name. Codes within
+/// a department often differ by a single digit, so a nearly-equal code alone is not enough.
+pub fn is_similar_course(code: &str, name: &str, other_code: &str, other_name: &str) -> bool {
+    (nearly_equal(code, other_code) && nearly_equal(name, other_name))
+        || name.to_lowercase() == other_name.to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn course_codes_normalize_to_the_same_form() {
+        for code in ["mat4170", "MAT 4170", "mat-4170", "Mat  4170", " MAT_4170 "] {
+            assert_eq!(
+                normalize_course_code(code).as_deref(),
+                Some("MAT 4170"),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn unusual_course_codes_are_uppercased() {
+        assert_eq!(
+            normalize_course_code("honors  seminar").as_deref(),
+            Some("HONORS SEMINAR")
+        );
+        assert_eq!(normalize_course_code("4170a").as_deref(), Some("4170A"));
+    }
+
+    #[test]
+    fn blank_course_codes_are_rejected() {
+        assert_eq!(normalize_course_code(""), None);
+        assert_eq!(normalize_course_code(" - "), None);
+    }
+
+    #[test]
+    fn names_are_trimmed_and_collapsed() {
+        assert_eq!(
+            normalize_name("  Abstract \t Algebra  I "),
+            "Abstract Algebra I"
+        );
+    }
+
+    #[test]
+    fn nearly_equal_allows_more_typos_in_longer_strings() {
+        assert!(nearly_equal("MAT 4170", "mat 4170"));
+        assert!(nearly_equal("MAT 4170", "MAT 4710"));
+        assert!(!nearly_equal("MAT 4170", "MAT 4710 "));
+        assert!(!nearly_equal("MAT 4170", "MTA 4710"));
+
+        assert!(nearly_equal("Abstract Algebra", "Abstarct Algebar"));
+        assert!(!nearly_equal("Abstract Algebra", "Abstarct Algebar I"));
+    }
+
+    #[test]
+    fn near_duplicate_courses_are_similar() {
+        // a typo in both the code and the name
+        assert!(is_similar_course(
+            "MAT 4170",
+            "Abstract Algebra I",
+            "MAT 4710",
+            "Abstract Algebar I"
+        ));
+
+        // the same name under a different code
+        assert!(is_similar_course(
+            "MAT 4170",
+            "Abstract Algebra I",
+            "MATH 417",
+            "abstract algebra i"
+        ));
+    }
+
+    #[test]
+    fn distinct_courses_are_not_similar() {
+        // neighboring codes in the same department
+        assert!(!is_similar_course(
+            "MAT 4170",
+            "Abstract Algebra I",
+            "MAT 4180",
+            "Real Analysis I"
+        ));
+
+        // similar names under unrelated codes
+        assert!(!is_similar_course(
+            "CS 2400",
+            "Data Structures",
+            "STA 2100",
+            "Data Structures Lab"
+        ));
+    }
+}
@@ -3,24 +3,23 @@ use std::net::SocketAddr;
 use color_eyre::{eyre::WrapErr, Result};
 
 use surrealdb::{
-    engine::{
-        local::{self, Db},
-        remote::ws::{self, Client},
-    },
+    engine::any::{self, Any},
     opt::auth::Root,
-    Surreal,
+    RecordId, Surreal,
 };
 
 use tracing::instrument;
 
-const DB_ROOT_PASS: &'static str = "root";
+use ulid::Ulid;
+
+const DB_ROOT_PASS: &str = "root";
 
 /// Create and return a connection to a SurrealDB database at the given address and port.
 #[instrument]
-pub async fn connect(db_addr: SocketAddr) -> Result<Surreal<Client>> {
+pub async fn connect(db_addr: SocketAddr) -> Result<Surreal<Any>> {
     info!("connecting to database");
 
-    let db = Surreal::new::<ws::Ws>(db_addr)
+    let db = any::connect(format!("ws://{db_addr}"))
         .await
         .wrap_err_with(|| format!("could not connect to database at {db_addr}"))?;
 
@@ -37,14 +36,45 @@ pub async fn connect(db_addr: SocketAddr) -> Result<Surreal<Client>> {
     Ok(db)
 }
 
-/// Create and return a connection to a temporary in-memory database, to be used in testing.
+/// Create and return a connection to a temporary in-memory database, to be used in testing. The
+/// tables are set up the same way as in `surql/setup_tables.surql`.
+#[cfg(test)]
 #[instrument]
-pub async fn in_memory() -> Result<Surreal<Db>> {
+pub async fn in_memory() -> Result<Surreal<Any>> {
     info!("creating in-memory database");
 
-    let db = Surreal::new::<local::Mem>(())
+    let db = any::connect("mem://")
         .await
         .wrap_err("failed to create in-memory database")?;
 
+    db.use_ns("unistellar").use_db("main").await?;
+
+    db.query(include_str!("../surql/setup_tables.surql"))
+        .await
+        .wrap_err("failed to set up tables")?
+        .check()
+        .wrap_err("failed to set up tables")?;
+
     Ok(db)
 }
+
+/// The ID of the record with the given ULID in the given table (e.g. "user:`<id>`"), for binding to
+/// a query parameter.
+pub fn record_id(table: &str, id: Ulid) -> RecordId {
+    RecordId::from_table_key(table, id.to_string())
+}
+
+/// Whether the given error came from a transaction which failed to commit because it conflicted
+/// with a concurrent transaction, in which case it may succeed if retried. Errors from a remote
+/// database only carry their message, so this has to match on that.
+pub fn is_transaction_conflict(err: &surrealdb::Error) -> bool {
+    err.to_string()
+        .contains("There was a problem with a datastore transaction")
+}
+
+/// Whether the given error came from a query violating the `UNIQUE` index with the given name.
+/// Errors from a remote database only carry their message, so this has to match on that.
+pub fn is_index_violation(err: &surrealdb::Error, index: &str) -> bool {
+    err.to_string()
+        .contains(&format!("Database index `{index}` already contains"))
+}
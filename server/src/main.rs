@@ -6,27 +6,38 @@ use std::{
     fs,
     net::SocketAddr,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use clap::Parser;
 
 use color_eyre::eyre::{bail, OptionExt, Result, WrapErr};
 
-use dirs_next;
+use rocket::{data::ByteUnit, Build, Rocket};
 
-use rocket::fs::FileServer;
-use surrealdb::Surreal;
+use surrealdb::{engine::any::Any, Surreal};
+
+use err::LogMapErr;
+use media_store::MediaStore;
 
 use tracing::{info, instrument, level_filters::LevelFilter};
 use tracing_appender::non_blocking::WorkerGuard;
 
+mod auth;
+mod courses;
 mod db;
 mod err;
 mod media;
+mod media_store;
 mod routes;
+mod search;
+mod stats;
 mod structs;
 
-const APP_NAME: &'static str = "unistellar-server";
+#[cfg(test)]
+mod testing;
+
+const APP_NAME: &str = "unistellar-server";
 
 /// A location to output logging.
 #[derive(clap::ValueEnum, Debug, Clone, PartialEq, Eq)]
@@ -61,9 +72,51 @@ struct Args {
     #[arg(value_enum, long, short = 'l')]
     log_to: Option<LogTo>,
 
-    /// Where to look for and store media such as profile pictures and course thumbnails.
+    /// Where to store media.
+    #[command(flatten)]
+    media_store: media_store::MediaStoreArgs,
+
+    /// If given, sweep orphaned media (see the `sweep-media` command) in the background this often,
+    /// starting one interval after the server starts.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    media_sweep_interval_hours: Option<u64>,
+
+    /// The total size of media, in MiB, that each user may have stored at once.
+    #[arg(long, default_value_t = 64)]
+    media_quota_mib: u64,
+
+    /// The number of media files that each user may upload per hour.
+    #[arg(long, default_value_t = 20)]
+    media_uploads_per_hour: u32,
+
+    /// Only allow users to enroll in courses at their own university.
     #[arg(long)]
-    media_dir: PathBuf,
+    restrict_enrollment_to_university: bool,
+
+    /// How much to favor search results related to the searcher.
+    #[command(flatten)]
+    search_boosts: search::SearchBoosts,
+
+    /// A maintenance task to run instead of launching the server.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// A maintenance task which runs against the database and then exits.
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Recompute every user's stats and assignment statuses from scratch by replaying the activity
+    /// log, overwrite any that differ, and print a report of the users which differed. No activity
+    /// may be registered, edited, or deleted while this runs (see `stats::repair`).
+    RepairStats,
+
+    /// Delete media files which are no longer referenced by anything in the database (e.g. old
+    /// profile pictures), and print a report of what was deleted.
+    SweepMedia(media::SweepOptions),
+
+    /// Make the user with the given username an admin, allowing them to use the "/api/admin/..."
+    /// routes.
+    GrantAdmin { username: String },
 }
 
 /// If the given path exists and is a directory, do nothing. If the given path does not exist,
@@ -79,7 +132,7 @@ fn ensure_dir_exists(path: impl AsRef<Path> + Debug) -> Result<()> {
     let path = path.as_ref();
 
     if !path.exists() {
-        fs::create_dir(&path).wrap_err_with(|| format!("failed to create directory {path:?}"))?;
+        fs::create_dir(path).wrap_err_with(|| format!("failed to create directory {path:?}"))?;
     } else if !path.is_dir() {
         bail!("{path:?} exists but is not a directory");
     }
@@ -164,26 +217,26 @@ fn init_logging(log_to: LogTo) -> Result<WorkerGuard> {
 struct State<C: surrealdb::Connection> {
     /// A connection to the main database.
     db: Surreal<C>,
-}
 
-#[rocket::main]
-async fn main() -> Result<()> {
-    // install custom error handler to improve error messages
-    color_eyre::install()?;
+    /// See [`Args::media_store`].
+    media_store: Arc<dyn MediaStore>,
 
-    // parse command-line arguments
-    let args = Args::parse();
+    /// See [`Args::media_quota_mib`].
+    media_quota: ByteUnit,
 
-    let _guard = init_logging(args.log_to.unwrap_or_default())?;
+    /// See [`Args::media_uploads_per_hour`].
+    media_uploads_per_hour: u32,
 
-    // connect to the database
-    let db = db::connect(args.db_addr).await?;
-
-    let state = State { db };
+    /// See [`Args::restrict_enrollment_to_university`].
+    restrict_enrollment_to_university: bool,
 
-    info!("launching server");
+    /// See [`Args::search_boosts`].
+    search_boosts: search::SearchBoosts,
+}
 
-    let _rocket = rocket::build()
+/// Build the server around the given state, with all of the routes mounted.
+fn rocket(state: State<Any>) -> Rocket<Build> {
+    rocket::build()
         .manage(state)
         .mount(
             "/api",
@@ -194,6 +247,10 @@ async fn main() -> Result<()> {
                 routes::user_courses,
                 routes::user_search,
                 routes::user_activity,
+                routes::user_feed,
+                routes::log_user_activity,
+                routes::edit_user_activity,
+                routes::delete_user_activity,
                 routes::user_stats,
                 routes::user_assignment_statuses,
                 routes::uni_students,
@@ -201,12 +258,90 @@ async fn main() -> Result<()> {
                 routes::uni_search,
                 routes::major_search,
                 routes::assignment_search,
+                routes::unified_search,
+                routes::admin_repair_stats,
+                routes::register,
+                routes::login,
+                routes::logout,
+                routes::me,
+                routes::follow,
+                routes::unfollow,
+                routes::enroll,
+                routes::unenroll,
+                routes::create_course,
+                routes::create_assignment,
+                routes::set_profile_picture,
             ],
         )
-        .mount("/media", FileServer::from(args.media_dir))
-        .launch()
-        .await
-        .wrap_err("server failure")?;
+        .mount("/media", routes![routes::media_file])
+}
+
+#[rocket::main]
+async fn main() -> Result<()> {
+    // install custom error handler to improve error messages
+    color_eyre::install()?;
+
+    // parse command-line arguments
+    let args = Args::parse();
+
+    let _guard = init_logging(args.log_to.unwrap_or_default())?;
+
+    // connect to the database
+    let db = db::connect(args.db_addr).await?;
+
+    let media_store = args.media_store.open()?;
+
+    if let Some(command) = args.command {
+        match command {
+            Command::RepairStats => {
+                let discrepancies = stats::repair(&db).await?;
+
+                println!("{}", serde_json::to_string_pretty(&discrepancies)?);
+            }
+            Command::SweepMedia(options) => {
+                let report = media::sweep_orphans(&db, &*media_store, &options).await?;
+
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            Command::GrantAdmin { username } => auth::grant_admin(&db, username).await?,
+        }
+
+        return Ok(());
+    }
+
+    if let Some(hours) = args.media_sweep_interval_hours {
+        let db = db.clone();
+        let media_store = media_store.clone();
+
+        rocket::tokio::spawn(async move {
+            // the first tick of `interval` completes immediately, but a sweep at every startup
+            // could make frequent restarts hammer the media store
+            let period = std::time::Duration::from_secs(hours * 60 * 60);
+            let start = rocket::tokio::time::Instant::now() + period;
+            let mut interval = rocket::tokio::time::interval_at(start, period);
+
+            loop {
+                interval.tick().await;
+
+                let _ = media::sweep_orphans(&db, &*media_store, &media::SweepOptions::default())
+                    .await
+                    .log_err();
+            }
+        });
+    }
+
+    let state = State {
+        db,
+        media_store,
+        media_quota: ByteUnit::Mebibyte(args.media_quota_mib),
+        media_uploads_per_hour: args.media_uploads_per_hour,
+        restrict_enrollment_to_university: args.restrict_enrollment_to_university,
+        search_boosts: args.search_boosts,
+    };
+
+    info!("launching server");
+
+    let _rocket = rocket(state).launch().await.wrap_err("server failure")?;
 
     Ok(())
 }
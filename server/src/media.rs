@@ -1,33 +1,1346 @@
-use crate::{Args, State};
+//! Storage of user-uploaded media such as profile pictures.
+//!
+//! Only image formats which we can positively identify from their magic bytes are accepted (see
+//! [`sniff_mime`]). The detected MIME type is stored in a `media` record, so that the media can be
+//! served back with the correct `Content-Type` without having to guess from a file extension.
+//!
+//! Many users upload the same images (e.g. default avatars), so media is content-addressed: the
+//! bytes of each distinct image are stored only once, as a "blob" keyed by their SHA-256 hash (see
+//! [`blob_key`]). Every upload still gets its own `media` record and ULID, which points at its
+//! blob, and each `blob` record counts how many `media` records point at it so that the blob can be
+//! deleted once the last of them is.
+//!
+//! Alongside each image, downscaled variants are stored for each of [`VARIANT_SIZES`] so that
+//! clients showing many images at once (e.g. a list of followers) don't have to download them at
+//! full resolution. These are served at "/media/<ulid>?size=<size>".
+//!
+//! Photos taken on phones carry metadata such as GPS coordinates and device information, so the
+//! stored original is never the uploaded file itself, but a re-encoding of its pixels (see
+//! [`strip_metadata`]).
+//!
+//! Each `media` record stores the user who uploaded it, so that the total size of each user's
+//! media and the rate at which they upload it can be limited (see [`MediaLimit`]).
 
-use std::fs::File;
+use crate::{
+    db::{self, record_id},
+    err::LogMapErr,
+    media_store::{LocalStore, MediaStore, ObjectStat},
+    State,
+};
 
-use color_eyre::eyre::{Result, WrapErr};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    io::Cursor,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
-use rocket::data::{Data, ToByteUnit};
+use chrono::{DateTime, TimeDelta, Utc};
 
-use surrealdb::engine::remote::ws::Client;
+use color_eyre::eyre::{self, OptionExt, WrapErr};
 
-use tracing::instrument;
+use image::{
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        jpeg::JpegEncoder,
+    },
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits,
+};
+
+use sha2::{Digest, Sha256};
+
+use rocket::{
+    data::{ByteUnit, Data},
+    tokio::{fs, task},
+};
+
+use surrealdb::{engine::any::Any, RecordId, Surreal};
+
+use tracing::{info, instrument, warn};
 use ulid::Ulid;
 
-#[instrument(level = "debug", skip_all)]
+/// The maximum size of a single media upload.
+pub const MAX_MEDIA_SIZE: ByteUnit = ByteUnit::Mebibyte(8);
+
+/// The maximum width and height of an uploaded image. A small file can still decode into a huge
+/// image, so this bounds the memory used while generating variants.
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+
+/// The sizes (in pixels, along the longer side) of the downscaled variants stored for each image.
+pub const VARIANT_SIZES: [u32; 2] = [64, 256];
+
+/// The quality (out of 100) at which JPEG images are re-encoded.
+const JPEG_QUALITY: u8 = 90;
+
+/// A reason that an upload could not be stored.
+#[derive(Debug)]
+pub enum MediaError {
+    /// The upload was larger than [`MAX_MEDIA_SIZE`].
+    TooLarge,
+
+    /// The upload was not in any format that we accept.
+    UnsupportedType,
+
+    /// The uploader is not allowed to store any more media right now.
+    LimitExceeded(MediaLimit),
+
+    /// Something went wrong on our end.
+    Internal(eyre::Report),
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge => write!(f, "upload exceeds {MAX_MEDIA_SIZE}"),
+            Self::UnsupportedType => write!(f, "upload is not in a supported format"),
+            Self::LimitExceeded(limit) => write!(f, "{limit}"),
+            Self::Internal(err) => write!(f, "{err:?}"),
+        }
+    }
+}
+
+/// A per-user limit on stored media which an upload would exceed. Serialized as an explanation for
+/// the client, tagged with the kind of limit as `limit`.
+#[derive(serde::Serialize, Debug)]
+#[serde(tag = "limit", rename_all = "snake_case")]
+pub enum MediaLimit {
+    /// Storing the upload would put the total size of the uploader's media over their quota
+    /// ([`State::media_quota`]).
+    Quota {
+        used_bytes: u64,
+        upload_bytes: u64,
+        quota_bytes: u64,
+    },
+
+    /// The uploader has already uploaded as much media in the last hour as they are allowed to
+    /// ([`State::media_uploads_per_hour`]).
+    Rate {
+        uploads_per_hour: u32,
+
+        /// How long until the uploader may upload again.
+        retry_after_secs: i64,
+    },
+}
+
+impl fmt::Display for MediaLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Quota {
+                used_bytes,
+                upload_bytes,
+                quota_bytes,
+            } => write!(
+                f,
+                "storing {upload_bytes} more bytes would exceed media quota \
+                ({used_bytes} of {quota_bytes} bytes used)"
+            ),
+            Self::Rate {
+                uploads_per_hour,
+                retry_after_secs,
+            } => write!(
+                f,
+                "media upload limit of {uploads_per_hour} per hour reached \
+                (retry in {retry_after_secs}s)"
+            ),
+        }
+    }
+}
+
+impl From<eyre::Report> for MediaError {
+    fn from(err: eyre::Report) -> Self {
+        Self::Internal(err)
+    }
+}
+
+/// Detect the MIME type of an image from its magic bytes. Returns `None` unless the data is a
+/// PNG, JPEG, GIF, or WebP image.
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some("image/png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// How much media a user has stored, and how recently.
+#[derive(serde::Deserialize, Debug)]
+struct MediaUsage {
+    /// The total size of the originals of the user's media. Variants are small in comparison, so
+    /// they are not counted against the user's quota.
+    used_bytes: u64,
+
+    /// The number of media the user has uploaded in the last hour.
+    uploads_last_hour: u32,
+
+    /// The time of the earliest of the user's uploads in the last hour, if any.
+    earliest_recent_upload: Option<DateTime<Utc>>,
+}
+
+/// Statements which set `$usage` to the [`MediaUsage`] of `$user`.
+const MEDIA_USAGE_QUERY: &str = "
+    LET $recent = SELECT VALUE uploaded FROM media
+        WHERE uploader = $user AND uploaded > time::now() - 1h
+        ORDER BY uploaded;
+
+    LET $usage = {
+        used_bytes: math::sum(SELECT VALUE size FROM media WHERE uploader = $user),
+        uploads_last_hour: array::len($recent),
+        earliest_recent_upload: $recent[0],
+    };";
+
+/// Look up how much media the given user has stored, and how recently.
+async fn media_usage(state: &State<Any>, user: Ulid) -> eyre::Result<MediaUsage> {
+    state
+        .db
+        .query(format!("{MEDIA_USAGE_QUERY} RETURN $usage;"))
+        .bind(("user", record_id("user", user)))
+        .await
+        .wrap_err("failed to query media usage")?
+        .take::<Option<MediaUsage>>(2)
+        .wrap_err("failed to deserialize media usage")?
+        .ok_or_eyre("media usage query returned nothing")
+}
+
+/// Check that a user with the given media usage may store an upload of the given size.
+fn check_limits(
+    state: &State<Any>,
+    usage: &MediaUsage,
+    upload_bytes: u64,
+) -> Result<(), MediaLimit> {
+    if usage.uploads_last_hour >= state.media_uploads_per_hour {
+        let retry_after = usage
+            .earliest_recent_upload
+            .map(|earliest| earliest + TimeDelta::hours(1) - Utc::now())
+            .unwrap_or_default();
+
+        return Err(MediaLimit::Rate {
+            uploads_per_hour: state.media_uploads_per_hour,
+            retry_after_secs: retry_after.num_seconds().max(0),
+        });
+    }
+
+    if usage.used_bytes + upload_bytes > state.media_quota.as_u64() {
+        return Err(MediaLimit::Quota {
+            used_bytes: usage.used_bytes,
+            upload_bytes,
+            quota_bytes: state.media_quota.as_u64(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Message of the error thrown by [`record_media`] when the upload would exceed a [`MediaLimit`].
+const LIMIT_EXCEEDED: &str = "media limit exceeded";
+
+/// Message of the error thrown by [`record_media`] when the blob is being swept.
+const BLOB_SWEEPING: &str = "blob is being swept";
+
+/// How long [`record_media`] waits before trying again when the blob is being swept.
+const SWEEP_WAIT: Duration = Duration::from_millis(200);
+
+/// How many times [`record_media`] tries to commit before giving up, e.g. on uploads which keep
+/// conflicting or on a blob which stays mid-sweep.
+const RECORD_MEDIA_ATTEMPTS: u32 = 50;
+
+/// Record a stored upload in a new `media` record with the given ID, pointing at the blob with the
+/// given content hash (which is created if it doesn't exist yet). The uploader's limits are checked
+/// again in the same transaction, since other uploads by them may have been recorded since the
+/// limits were first checked.
+///
+/// If [`sweep_orphans`] has claimed the blob, this waits for the sweep to delete it and then
+/// records it anew, so that the caller can safely store its files afterwards.
+#[instrument(level = "debug", skip(state))]
+async fn record_media(
+    state: &State<Any>,
+    uploader: Ulid,
+    media: Ulid,
+    hash: &str,
+    mime: &'static str,
+    size: u64,
+) -> Result<(), MediaError> {
+    // every upload writes to the uploader's record, so that concurrent uploads by the same user
+    // conflict and are retried instead of all passing the limits. `refs` of the blob is
+    // incremented by the `count_blob_refs` event
+    let query = format!(
+        "BEGIN TRANSACTION;
+
+        UPDATE $user SET last_media_upload = time::now();
+
+        {MEDIA_USAGE_QUERY}
+
+        IF $usage.uploads_last_hour >= $uploads_per_hour OR $usage.used_bytes + $size > $quota {{
+            THROW '{LIMIT_EXCEEDED}';
+        }};
+
+        IF $blob.sweeping > time::now() - {SWEEP_CLAIM_TIMEOUT} {{
+            THROW '{BLOB_SWEEPING}';
+        }};
+
+        UPSERT $blob SET mime = $mime, size = $size, sweeping = NONE;
+
+        CREATE $media SET
+            blob = $blob,
+            mime = $mime,
+            size = $size,
+            uploader = $user,
+            uploaded = time::now();
+
+        COMMIT TRANSACTION;"
+    );
+
+    for attempt in 1..=RECORD_MEDIA_ATTEMPTS {
+        let errors = state
+            .db
+            .query(&query)
+            .bind(("user", record_id("user", uploader)))
+            .bind(("uploads_per_hour", state.media_uploads_per_hour))
+            .bind(("quota", state.media_quota.as_u64()))
+            .bind(("blob", RecordId::from_table_key("blob", hash)))
+            .bind(("media", record_id("media", media)))
+            .bind(("mime", mime))
+            .bind(("size", size))
+            .await
+            .wrap_err("failed to create media record")?
+            .take_errors();
+
+        // the statement which failed is not necessarily the first with an error, since the others
+        // in the transaction are all cancelled
+        if errors.is_empty() {
+            return Ok(());
+        } else if errors
+            .values()
+            .any(|err| err.to_string().contains(LIMIT_EXCEEDED))
+        {
+            let usage = media_usage(state, uploader).await?;
+
+            check_limits(state, &usage, size).map_err(MediaError::LimitExceeded)?;
+
+            debug!("media usage went down while recording upload (attempt {attempt})");
+        } else if errors
+            .values()
+            .any(|err| err.to_string().contains(BLOB_SWEEPING))
+        {
+            debug!("waiting for blob {hash} to be swept (attempt {attempt})");
+
+            rocket::tokio::time::sleep(SWEEP_WAIT).await;
+        } else if errors.values().any(db::is_transaction_conflict) {
+            debug!("upload conflicted with another (attempt {attempt})");
+        } else {
+            let err = errors.into_values().next().expect("errors is not empty");
+            return Err(eyre::Report::new(err)
+                .wrap_err("failed to create media record")
+                .into());
+        }
+    }
+
+    Err(eyre::eyre!("failed to create media record after {RECORD_MEDIA_ATTEMPTS} attempts").into())
+}
+
+/// Lowercase hex encoding of the SHA-256 digest of some media, which identifies the blob that it is
+/// stored in.
+fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Get the key under which the blob with the given content hash is stored in the [`MediaStore`],
+/// or its variant of the given size.
+pub fn blob_key(hash: &str, size: Option<u32>) -> String {
+    match size {
+        None => hash.to_owned(),
+        Some(size) => format!("{hash}_{size}"),
+    }
+}
+
+/// Get the content hash of the blob that the object with the given key belongs to, i.e. the
+/// inverse of [`blob_key`]. Returns `None` for keys that no blob object would have.
+fn blob_hash_of_key(key: &str) -> Option<&str> {
+    let (hash, size) = match key.split_once('_') {
+        Some((hash, size)) => (hash, Some(size)),
+        None => (key, None),
+    };
+
+    if size.is_some_and(|size| size.parse::<u32>().is_err()) {
+        return None;
+    }
+
+    let is_hash = hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+
+    is_hash.then_some(hash)
+}
+
+/// Limits on decoding uploaded images.
+fn decoding_limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits
+}
+
+/// Decode an image of the given MIME type, rotating it upright according to its metadata.
+fn decode_image(bytes: &[u8], mime: &str) -> eyre::Result<DynamicImage> {
+    let format = ImageFormat::from_mime_type(mime).ok_or_eyre("unsupported MIME type")?;
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(decoding_limits());
+
+    let mut decoder = reader.into_decoder().wrap_err("failed to read image")?;
+    let orientation = decoder
+        .orientation()
+        .wrap_err("failed to read image orientation")?;
+
+    let mut image = DynamicImage::from_decoder(decoder).wrap_err("failed to decode image")?;
+    image.apply_orientation(orientation);
+
+    Ok(image)
+}
+
+/// Encode an image in the format of the given MIME type.
+fn encode_image(image: &DynamicImage, mime: &str) -> eyre::Result<Vec<u8>> {
+    let format = ImageFormat::from_mime_type(mime).ok_or_eyre("unsupported MIME type")?;
+
+    let mut bytes = Cursor::new(Vec::new());
+
+    // the JPEG encoder does not support alpha channels
+    if format == ImageFormat::Jpeg {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY))
+    } else {
+        image.write_to(&mut bytes, format)
+    }
+    .wrap_err("failed to encode image")?;
+
+    Ok(bytes.into_inner())
+}
+
+/// Re-encode an uploaded image from its pixels alone, so that none of its metadata (EXIF incl. GPS
+/// coordinates, XMP, PNG text and iCCP chunks, GIF comments, etc.) is kept. `image` must be the
+/// result of [`decode_image`] on `bytes`, which has already applied any orientation metadata. GIFs
+/// are re-encoded frame by frame from `bytes` so that animations are kept.
+fn strip_metadata(bytes: &[u8], image: &DynamicImage, mime: &str) -> eyre::Result<Vec<u8>> {
+    if mime != "image/gif" {
+        return encode_image(image, mime);
+    }
+
+    let mut decoder = GifDecoder::new(Cursor::new(bytes)).wrap_err("failed to read GIF")?;
+    decoder
+        .set_limits(decoding_limits())
+        .wrap_err("failed to set GIF decoding limits")?;
+
+    let mut stripped = Vec::new();
+
+    let mut encoder = GifEncoder::new(&mut stripped);
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .wrap_err("failed to encode GIF")?;
+    encoder
+        .try_encode_frames(decoder.into_frames())
+        .wrap_err("failed to encode GIF")?;
+    drop(encoder);
+
+    Ok(stripped)
+}
+
+/// Downscale an image so that it fits within a square of the given size, in the same format.
+/// Images which already fit are only re-encoded.
+fn make_variant(image: &DynamicImage, mime: &str, size: u32) -> eyre::Result<Vec<u8>> {
+    if image.width() <= size && image.height() <= size {
+        encode_image(image, mime)
+    } else {
+        encode_image(&image.thumbnail(size, size), mime)
+    }
+}
+
+/// Read the blob with the given content hash and MIME type, or its variant of the given size.
+/// Missing variants (e.g. of media stored before variants existed) are generated on demand.
+#[instrument(level = "debug", skip(state))]
+pub async fn read_blob(
+    state: &State<Any>,
+    hash: &str,
+    mime: &str,
+    size: Option<u32>,
+) -> eyre::Result<Vec<u8>> {
+    let key = blob_key(hash, size);
+
+    if let Some(bytes) = state.media_store.get(&key).await? {
+        return Ok(bytes);
+    }
+
+    let Some(size) = size else {
+        eyre::bail!("blob {hash} is missing from the media store");
+    };
+
+    debug!("generating missing variant of size {size}");
+
+    let bytes = state
+        .media_store
+        .get(&blob_key(hash, None))
+        .await?
+        .ok_or_eyre("original of blob variant is missing from the media store")?;
+
+    let mime = mime.to_owned();
+
+    let variant =
+        task::spawn_blocking(move || make_variant(&decode_image(&bytes, &mime)?, &mime, size))
+            .await??;
+
+    state.media_store.put(&key, variant.clone()).await?;
+
+    Ok(variant)
+}
+
+/// Store the files of the blob with the given content hash, i.e. the given metadata-stripped image
+/// and its variants, unless they are already stored.
+async fn store_blob(
+    state: &State<Any>,
+    hash: &str,
+    mime: &'static str,
+    image: DynamicImage,
+    bytes: Vec<u8>,
+) -> eyre::Result<()> {
+    // once the blob is recorded with a reference to it, sweeps leave its files alone
+    if state
+        .media_store
+        .stat(&blob_key(hash, None))
+        .await?
+        .is_some()
+    {
+        debug!("reusing existing blob {hash}");
+        return Ok(());
+    }
+
+    debug!("storing new {mime} blob {hash}");
+
+    let variants = task::spawn_blocking(move || {
+        VARIANT_SIZES
+            .into_iter()
+            .map(|size| Ok((size, make_variant(&image, mime, size)?)))
+            .collect::<eyre::Result<Vec<_>>>()
+    })
+    .await
+    .wrap_err("failed to join image processing task")??;
+
+    state.media_store.put(&blob_key(hash, None), bytes).await?;
+
+    for (size, variant) in variants {
+        state
+            .media_store
+            .put(&blob_key(hash, Some(size)), variant)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Validate an upload by the given user and store it in the media store (unless identical media is
+/// already stored), recording its blob, detected MIME type, and uploader in a `media` record under a
+/// fresh ULID. Returns that ULID. Media is served back at "/media/<ulid>".
+#[instrument(level = "debug", skip(state, data))]
 pub async fn store_media(
-    args: &Args,
-    state: &rocket::State<State<Client>>,
+    state: &State<Any>,
+    uploader: Ulid,
     data: Data<'_>,
-) -> Result<()> {
+) -> Result<Ulid, MediaError> {
+    let usage = media_usage(state, uploader).await?;
+
+    // check the limits before reading the upload so that spamming uploads stays cheap
+    check_limits(state, &usage, 0).map_err(MediaError::LimitExceeded)?;
+
+    let bytes = data
+        .open(MAX_MEDIA_SIZE)
+        .into_bytes()
+        .await
+        .wrap_err("failed to read media data")?;
+
+    if !bytes.is_complete() {
+        return Err(MediaError::TooLarge);
+    }
+
+    let mime = sniff_mime(&bytes).ok_or(MediaError::UnsupportedType)?;
+
+    let bytes = bytes.into_inner();
+
+    // decoding and re-encoding is CPU-heavy, so keep it off of the async workers
+    let (image, bytes) = task::spawn_blocking(move || {
+        let image = decode_image(&bytes, mime)?;
+
+        let bytes = strip_metadata(&bytes, &image, mime)?;
+
+        eyre::Ok((image, bytes))
+    })
+    .await
+    .wrap_err("failed to join image processing task")?
+    .map_err(|err| {
+        debug!("failed to process image: {err:?}");
+        MediaError::UnsupportedType
+    })?;
+
+    let size = bytes.len() as u64;
+
+    check_limits(state, &usage, size).map_err(MediaError::LimitExceeded)?;
+
+    let hash = content_hash(&bytes);
+
     let media_ulid = Ulid::new();
 
-    let mut media_path = args.media_dir.clone();
-    media_path.push(media_ulid.to_string());
+    // the blob is recorded before its files are stored, so that a sweep can't delete them from
+    // under it (see `sweep_orphans`)
+    record_media(state, uploader, media_ulid, &hash, mime, size).await?;
 
-    debug!("writing media to {media_path:?}");
+    if let Err(err) = store_blob(state, &hash, mime, image, bytes).await {
+        // the media is useless without its files, so release it and leave the rest to the sweep
+        let _ = state
+            .db
+            .query("DELETE $media")
+            .bind(("media", record_id("media", media_ulid)))
+            .await
+            .log_err();
 
-    data.open(8.mebibytes())
-        .into_file(media_path)
-        .await
-        .wrap_err("failed to write media data to file")?;
+        return Err(err.into());
+    }
+
+    Ok(media_ulid)
+}
+
+/// Every field of every table which holds the ID of a media file. Media which is not referenced by
+/// any of these is an orphan and gets swept by [`sweep_orphans`].
+const MEDIA_REFERENCES: &[(&str, &str)] = &[("user", "profile_picture")];
+
+/// Options for [`sweep_orphans`].
+#[derive(clap::Args, Debug, Clone)]
+pub struct SweepOptions {
+    /// Only report which media would be swept, without touching anything.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Media uploaded less than this many hours ago is never swept, since it may just not have
+    /// been referenced yet.
+    #[arg(long, default_value_t = 24)]
+    pub grace_period_hours: u64,
+
+    /// Move swept media files into this local directory instead of deleting them.
+    #[arg(long)]
+    pub quarantine_dir: Option<PathBuf>,
+}
+
+impl Default for SweepOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            grace_period_hours: 24,
+            quarantine_dir: None,
+        }
+    }
+}
+
+/// The result of [`sweep_orphans`].
+#[derive(serde::Serialize, Debug, Default)]
+pub struct SweepReport {
+    /// Whether this was a dry run, in which case nothing was actually swept.
+    pub dry_run: bool,
+
+    /// IDs of the orphaned media which were swept.
+    pub swept: Vec<Ulid>,
+
+    /// Content hashes of the blobs which were deleted because no media referred to them anymore.
+    pub freed_blobs: Vec<String>,
+
+    /// The total number of files (originals and variants) which were deleted from the media store.
+    pub files: usize,
+
+    /// The total size of the files which were deleted from the media store.
+    pub bytes: u64,
+}
+
+/// How long a sweep's claim on a blob holds off uploads of that blob (see [`sweep_orphans`]).
+/// Sweeping a blob only takes as long as deleting its few files, so an older claim must be left
+/// over from a sweep which was interrupted.
+const SWEEP_CLAIM_TIMEOUT: &str = "1m";
+
+/// Delete (or quarantine) an object swept by [`sweep_orphans`], and count it in the report.
+async fn remove_object(
+    media_store: &dyn MediaStore,
+    quarantine: Option<&LocalStore>,
+    object: &ObjectStat,
+    options: &SweepOptions,
+    report: &mut SweepReport,
+) -> eyre::Result<()> {
+    if !options.dry_run {
+        if let Some(quarantine) = quarantine {
+            if let Some(bytes) = media_store.get(&object.key).await? {
+                quarantine.put(&object.key, bytes).await?;
+            }
+        }
+
+        media_store.delete(&object.key).await?;
+    }
+
+    report.files += 1;
+    report.bytes += object.size;
 
     Ok(())
 }
+
+/// Find media which is not referenced by any of [`MEDIA_REFERENCES`] and is older than the grace
+/// period, and delete its `media` records. Then, delete or quarantine the files of any blobs which
+/// are no longer referred to by any media, along with any files in the media store that don't
+/// belong to a blob at all (e.g. left over from an interrupted upload).
+///
+/// The same blob may be uploaded again while it is being swept. To keep the sweep from deleting
+/// the files of the new upload, each blob is first claimed by setting its `sweeping` time, then its
+/// files are deleted, and only then is its record deleted. [`store_media`] records the blob before
+/// storing its files, and waits while the blob is claimed.
+#[instrument(skip(db))]
+pub async fn sweep_orphans(
+    db: &Surreal<Any>,
+    media_store: &dyn MediaStore,
+    options: &SweepOptions,
+) -> eyre::Result<SweepReport> {
+    #[derive(serde::Deserialize)]
+    struct MediaBlob {
+        id: Ulid,
+        blob: String,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct BlobRefs {
+        id: String,
+        refs: i64,
+    }
+
+    let mut referenced = BTreeSet::new();
+
+    for &(table, field) in MEDIA_REFERENCES {
+        let ids: Vec<Ulid> = db
+            .query(
+                "SELECT VALUE type::field($field) FROM type::table($table)
+                WHERE type::field($field) != NONE",
+            )
+            .bind(("table", table))
+            .bind(("field", field))
+            .await
+            .wrap_err_with(|| format!("failed to query {table}.{field}"))?
+            .take(0)
+            .wrap_err_with(|| format!("failed to deserialize {table}.{field}"))?;
+
+        referenced.extend(ids);
+    }
+
+    let mut response = db
+        .query("SELECT record::id(id) AS id, record::id(blob) AS blob FROM media")
+        .query("SELECT record::id(id) AS id, refs FROM blob")
+        .await
+        .wrap_err("failed to query media and blobs")?;
+
+    let media: Vec<MediaBlob> = response.take(0).wrap_err("failed to deserialize media")?;
+    let blobs: Vec<BlobRefs> = response.take(1).wrap_err("failed to deserialize blobs")?;
+
+    let grace_period = Duration::from_secs(options.grace_period_hours * 60 * 60);
+    let now = SystemTime::now();
+    let is_old = |time: SystemTime| now.duration_since(time).unwrap_or_default() >= grace_period;
+
+    let orphans: Vec<&MediaBlob> = media
+        .iter()
+        .filter(|media| !referenced.contains(&media.id) && is_old(media.id.datetime()))
+        .collect();
+
+    // work out which blobs will have no references left once the orphans are gone
+    let mut refs: BTreeMap<&str, i64> = blobs
+        .iter()
+        .map(|blob| (blob.id.as_str(), blob.refs))
+        .collect();
+
+    for orphan in &orphans {
+        if let Some(refs) = refs.get_mut(orphan.blob.as_str()) {
+            *refs -= 1;
+        }
+    }
+
+    let mut report = SweepReport {
+        dry_run: options.dry_run,
+        ..SweepReport::default()
+    };
+
+    for orphan in orphans {
+        info!("sweeping orphaned media {}", orphan.id);
+
+        if !options.dry_run {
+            // `refs` of the blob is decremented by the `count_blob_refs` event
+            db.query("DELETE $media")
+                .bind(("media", record_id("media", orphan.id)))
+                .await
+                .wrap_err("failed to delete media record")?
+                .check()
+                .wrap_err("failed to delete media record")?;
+        }
+
+        report.swept.push(orphan.id);
+    }
+
+    let quarantine = match &options.quarantine_dir {
+        Some(quarantine_dir) if !options.dry_run => {
+            fs::create_dir_all(quarantine_dir).await.wrap_err_with(|| {
+                format!("failed to create quarantine directory {quarantine_dir:?}")
+            })?;
+
+            Some(LocalStore::new(quarantine_dir.clone()))
+        }
+        _ => None,
+    };
+
+    let mut objects: BTreeMap<&str, Vec<&ObjectStat>> = BTreeMap::new();
+
+    let listed = media_store.list().await?;
+
+    for object in &listed {
+        if let Some(hash) = blob_hash_of_key(&object.key) {
+            objects.entry(hash).or_default().push(object);
+        }
+    }
+
+    for (&hash, &refs) in &refs {
+        if refs > 0 {
+            continue;
+        }
+
+        let blob = RecordId::from_table_key("blob", hash);
+
+        if !options.dry_run {
+            // claim the blob so that uploads of it wait until its files are gone. it may have been
+            // uploaded again since we counted its references, in which case it must be kept
+            let claimed: Vec<String> = db
+                .query(
+                    "UPDATE $blob SET sweeping = time::now() WHERE refs <= 0
+                    RETURN VALUE record::id(id)",
+                )
+                .bind(("blob", blob.clone()))
+                .await
+                .wrap_err("failed to claim blob")?
+                .take(0)
+                .wrap_err("failed to claim blob")?;
+
+            if claimed.is_empty() {
+                continue;
+            }
+        }
+
+        info!("freeing blob {hash}");
+
+        for object in objects.remove(hash).unwrap_or_default() {
+            remove_object(
+                media_store,
+                quarantine.as_ref(),
+                object,
+                options,
+                &mut report,
+            )
+            .await?;
+        }
+
+        if !options.dry_run {
+            db.query("DELETE $blob WHERE sweeping != NONE AND refs <= 0")
+                .bind(("blob", blob))
+                .await
+                .wrap_err("failed to delete blob record")?
+                .check()
+                .wrap_err("failed to delete blob record")?;
+        }
+
+        report.freed_blobs.push(hash.to_owned());
+    }
+
+    // files which don't belong to any blob (e.g. left over from an interrupted upload)
+    for (hash, stray) in objects {
+        if refs.contains_key(hash) {
+            continue;
+        }
+
+        for object in stray {
+            if is_old(object.modified) {
+                remove_object(
+                    media_store,
+                    quarantine.as_ref(),
+                    object,
+                    options,
+                    &mut report,
+                )
+                .await?;
+            }
+        }
+    }
+
+    if report.swept.is_empty() && report.files == 0 {
+        info!("found no orphaned media");
+    } else {
+        warn!(
+            "swept {} orphaned media and {} blobs ({} files, {} bytes){}",
+            report.swept.len(),
+            report.freed_blobs.len(),
+            report.files,
+            report.bytes,
+            if options.dry_run { " (dry run)" } else { "" }
+        );
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{self, TestServer, TestUser};
+
+    use std::sync::{Arc, Mutex};
+
+    use rocket::{
+        http::{ContentType, Status},
+        tokio::{self, task::JoinHandle},
+    };
+
+    use tempfile::TempDir;
+
+    /// A JPEG with an APP1 segment holding EXIF data, including GPS coordinates.
+    const GPS_JPEG: &[u8] = include_bytes!("../tests/fixtures/gps.jpg");
+
+    /// A PNG with a tEXt chunk and an eXIf chunk holding EXIF data, including GPS coordinates.
+    const METADATA_PNG: &[u8] = include_bytes!("../tests/fixtures/metadata.png");
+
+    /// The markers of the segments of a JPEG image, up to the start of its scan data.
+    fn jpeg_markers(bytes: &[u8]) -> Vec<u8> {
+        let mut markers = Vec::new();
+        let mut rest = bytes.strip_prefix(&[0xFF, 0xD8]).expect("not a JPEG");
+
+        while let [0xFF, marker, len_hi, len_lo, ..] = *rest {
+            markers.push(marker);
+
+            if marker == 0xDA {
+                break;
+            }
+
+            rest = &rest[2 + u16::from_be_bytes([len_hi, len_lo]) as usize..];
+        }
+
+        markers
+    }
+
+    /// The types of the chunks of a PNG image.
+    fn png_chunks(bytes: &[u8]) -> Vec<[u8; 4]> {
+        let mut chunks = Vec::new();
+        let mut rest = &bytes[8..];
+
+        while let [l0, l1, l2, l3, t0, t1, t2, t3, ..] = *rest {
+            chunks.push([t0, t1, t2, t3]);
+            rest = &rest[12 + u32::from_be_bytes([l0, l1, l2, l3]) as usize..];
+        }
+
+        chunks
+    }
+
+    /// The EXIF data of an image, as read by its decoder.
+    fn exif(bytes: &[u8]) -> Option<Vec<u8>> {
+        ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .unwrap()
+            .exif_metadata()
+            .unwrap()
+    }
+
+    /// Create a user on the given server.
+    async fn user(server: &TestServer) -> TestUser {
+        let university = server.university("Cal Poly Pomona").await;
+        let major = server.major("Mathematics").await;
+
+        server
+            .user("jacobhenn", ("Jacob", "Henn"), university, major)
+            .await
+    }
+
+    /// Upload an image as the given user's profile picture, returning the status and, if
+    /// successful, the media ID.
+    async fn set_profile_picture(
+        server: &TestServer,
+        user: &TestUser,
+        bytes: &[u8],
+    ) -> (Status, Option<Ulid>) {
+        let response = server
+            .client
+            .put(format!("/api/user/{}/profile_picture", user.id))
+            .header(ContentType::Binary)
+            .header(user.auth())
+            .body(bytes)
+            .dispatch()
+            .await;
+
+        (response.status(), response.into_json().await)
+    }
+
+    /// The content hash of the blob of the given media.
+    async fn blob_of(server: &TestServer, media: Ulid) -> String {
+        let hash: Option<String> = server
+            .db()
+            .query("SELECT VALUE record::id(blob) FROM ONLY $media")
+            .bind(("media", record_id("media", media)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        hash.expect("media does not exist")
+    }
+
+    /// The stored original and each variant of the blob with the given content hash.
+    async fn blob_files(media_store: &dyn MediaStore, hash: &str) -> Vec<Vec<u8>> {
+        let mut files = Vec::new();
+
+        for size in [None].into_iter().chain(VARIANT_SIZES.map(Some)) {
+            let bytes = media_store.get(&blob_key(hash, size)).await.unwrap();
+            files.push(bytes.expect("blob file is missing"));
+        }
+
+        files
+    }
+
+    /// Upload an image as a profile picture, returning the stored original and each variant.
+    async fn upload(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let server = TestServer::new().await;
+        let user = user(&server).await;
+
+        let (status, media) = set_profile_picture(&server, &user, bytes).await;
+        assert_eq!(status, Status::Ok);
+
+        let hash = blob_of(&server, media.unwrap()).await;
+
+        blob_files(&*server.state().media_store, &hash).await
+    }
+
+    #[rocket::async_test]
+    async fn strips_exif_from_jpeg() {
+        assert!(jpeg_markers(GPS_JPEG).contains(&0xE1));
+        assert!(exif(GPS_JPEG).is_some());
+
+        for bytes in upload(GPS_JPEG).await {
+            assert!(!jpeg_markers(&bytes).contains(&0xE1));
+            assert_eq!(exif(&bytes), None);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn strips_text_and_exif_from_png() {
+        assert!(png_chunks(METADATA_PNG).contains(b"tEXt"));
+        assert!(exif(METADATA_PNG).is_some());
+
+        for bytes in upload(METADATA_PNG).await {
+            let chunks = png_chunks(&bytes);
+
+            for chunk in [b"tEXt", b"zTXt", b"iTXt", b"eXIf"] {
+                assert!(
+                    !chunks.contains(chunk),
+                    "{}",
+                    String::from_utf8_lossy(chunk)
+                );
+            }
+
+            assert_eq!(exif(&bytes), None);
+        }
+    }
+
+    #[test]
+    fn sniffs_supported_images() {
+        let cases: [(&[u8], &str); 5] = [
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "image/png"),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF\0", "image/jpeg"),
+            (b"GIF87a\x01\0\x01\0", "image/gif"),
+            (b"GIF89a\x01\0\x01\0", "image/gif"),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", "image/webp"),
+        ];
+
+        for (data, mime) in cases {
+            assert_eq!(sniff_mime(data), Some(mime), "{data:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let cases: [&[u8]; 5] = [
+            b"",
+            b"\x89PNG\r\n\x1a",
+            b"\xff\xd8",
+            b"GIF89",
+            b"RIFF\x24\0\0\0WEB",
+        ];
+
+        for data in cases {
+            assert_eq!(sniff_mime(data), None, "{data:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let cases: [&[u8]; 4] = [
+            b"<!DOCTYPE html><script>alert(1)</script>",
+            b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            b"%PDF-1.7\n",
+            b"RIFF\x24\0\0\0WAVEfmt ",
+        ];
+
+        for data in cases {
+            assert_eq!(sniff_mime(data), None, "{data:?}");
+        }
+    }
+
+    /// A media store which runs a hook before its first deletion, to make something happen in the
+    /// middle of a sweep.
+    struct HookedStore {
+        inner: LocalStore,
+        before_delete: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    }
+
+    impl fmt::Debug for HookedStore {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("HookedStore")
+                .field("inner", &self.inner)
+                .finish_non_exhaustive()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MediaStore for HookedStore {
+        async fn put(&self, key: &str, bytes: Vec<u8>) -> eyre::Result<()> {
+            self.inner.put(key, bytes).await
+        }
+
+        async fn get(&self, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+            self.inner.get(key).await
+        }
+
+        async fn delete(&self, key: &str) -> eyre::Result<()> {
+            let hook = self.before_delete.lock().unwrap().take();
+
+            if let Some(hook) = hook {
+                hook();
+
+                // give whatever the hook started a chance to get ahead of the deletion
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+
+            self.inner.delete(key).await
+        }
+
+        async fn stat(&self, key: &str) -> eyre::Result<Option<ObjectStat>> {
+            self.inner.stat(key).await
+        }
+
+        async fn list(&self) -> eyre::Result<Vec<ObjectStat>> {
+            self.inner.list().await
+        }
+    }
+
+    /// Upload a profile picture for the given user and then unset it, returning the ID of the
+    /// orphaned media and the content hash of its blob.
+    async fn orphan(server: &TestServer, user: &TestUser) -> (Ulid, String) {
+        let (status, media) = set_profile_picture(server, user, &testing::png(16, 16)).await;
+        assert_eq!(status, Status::Ok);
+
+        server
+            .db()
+            .query("UPDATE $user SET profile_picture = NONE")
+            .bind(("user", record_id("user", user.id)))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let media = media.unwrap();
+        let hash = blob_of(server, media).await;
+
+        (media, hash)
+    }
+
+    /// Whether a media record with the given ID exists.
+    async fn media_exists(server: &TestServer, media: Ulid) -> bool {
+        let id: Option<Ulid> = server
+            .db()
+            .query("SELECT VALUE record::id(id) FROM ONLY $media")
+            .bind(("media", record_id("media", media)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        id.is_some()
+    }
+
+    #[rocket::async_test]
+    async fn sweep_keeps_orphans_within_grace_period() {
+        let server = TestServer::new().await;
+        let user = user(&server).await;
+        let media_store = &*server.state().media_store;
+
+        let (media, hash) = orphan(&server, &user).await;
+
+        let report = sweep_orphans(server.db(), media_store, &SweepOptions::default())
+            .await
+            .unwrap();
+
+        assert!(report.swept.is_empty());
+        assert!(report.freed_blobs.is_empty());
+        assert_eq!(report.files, 0);
+        assert!(media_exists(&server, media).await);
+        assert_eq!(
+            blob_files(media_store, &hash).await.len(),
+            1 + VARIANT_SIZES.len()
+        );
+
+        let options = SweepOptions {
+            grace_period_hours: 0,
+            ..SweepOptions::default()
+        };
+
+        let report = sweep_orphans(server.db(), media_store, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.swept, [media]);
+        assert_eq!(report.freed_blobs, [hash.as_str()]);
+        assert!(!media_exists(&server, media).await);
+        assert!(media_store.list().await.unwrap().is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn dry_run_sweeps_nothing() {
+        let server = TestServer::new().await;
+        let user = user(&server).await;
+        let media_store = &*server.state().media_store;
+
+        let (media, hash) = orphan(&server, &user).await;
+        let files = blob_files(media_store, &hash).await;
+
+        let options = SweepOptions {
+            dry_run: true,
+            grace_period_hours: 0,
+            ..SweepOptions::default()
+        };
+
+        let report = sweep_orphans(server.db(), media_store, &options)
+            .await
+            .unwrap();
+
+        assert!(report.dry_run);
+        assert_eq!(report.swept, [media]);
+        assert_eq!(report.freed_blobs, [hash.as_str()]);
+        assert_eq!(report.files, files.len());
+        assert_eq!(
+            report.bytes,
+            files.iter().map(Vec::len).sum::<usize>() as u64
+        );
+
+        assert!(media_exists(&server, media).await);
+        assert_eq!(blob_files(media_store, &hash).await, files);
+    }
+
+    #[rocket::async_test]
+    async fn sweep_moves_files_into_quarantine() {
+        let server = TestServer::new().await;
+        let user = user(&server).await;
+        let media_store = &*server.state().media_store;
+
+        let (_, hash) = orphan(&server, &user).await;
+        let files = blob_files(media_store, &hash).await;
+
+        let quarantine_dir = TempDir::new().unwrap();
+
+        let options = SweepOptions {
+            grace_period_hours: 0,
+            quarantine_dir: Some(quarantine_dir.path().to_owned()),
+            ..SweepOptions::default()
+        };
+
+        let report = sweep_orphans(server.db(), media_store, &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.files, files.len());
+        assert!(media_store.list().await.unwrap().is_empty());
+
+        let quarantine = LocalStore::new(quarantine_dir.path().to_owned());
+        assert_eq!(blob_files(&quarantine, &hash).await, files);
+    }
+
+    #[rocket::async_test]
+    async fn sweep_keeps_blob_uploaded_during_sweep() {
+        let dir = TempDir::new().unwrap();
+
+        let store = Arc::new(HookedStore {
+            inner: LocalStore::new(dir.path().to_owned()),
+            before_delete: Mutex::new(None),
+        });
+
+        let server = Arc::new(TestServer::with(|state| state.media_store = store.clone()).await);
+        let user = user(&server).await;
+
+        let old = testing::png(16, 16);
+
+        // replacing the old picture leaves its blob without references
+        for picture in [&old, &testing::png(17, 17)] {
+            let (status, _) = set_profile_picture(&server, &user, picture).await;
+            assert_eq!(status, Status::Ok);
+        }
+
+        let upload: Arc<Mutex<Option<JoinHandle<_>>>> = Arc::default();
+
+        *store.before_delete.lock().unwrap() = Some(Box::new({
+            let server = server.clone();
+            let upload = upload.clone();
+
+            move || {
+                let handle =
+                    tokio::spawn(async move { set_profile_picture(&server, &user, &old).await });
+
+                *upload.lock().unwrap() = Some(handle);
+            }
+        }));
+
+        let options = SweepOptions {
+            grace_period_hours: 0,
+            ..SweepOptions::default()
+        };
+
+        let report = sweep_orphans(server.db(), &*store, &options).await.unwrap();
+        assert_eq!(report.freed_blobs.len(), 1);
+
+        let handle = upload
+            .lock()
+            .unwrap()
+            .take()
+            .expect("sweep deleted nothing");
+        let (status, media) = handle.await.unwrap();
+        assert_eq!(status, Status::Ok);
+
+        let hash = blob_of(&server, media.unwrap()).await;
+        assert_eq!(report.freed_blobs, [hash.as_str()]);
+
+        let refs: Option<i64> = server
+            .db()
+            .query("SELECT VALUE refs FROM ONLY $blob")
+            .bind(("blob", RecordId::from_table_key("blob", hash.clone())))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        assert_eq!(refs, Some(1));
+        assert_eq!(
+            blob_files(&*store, &hash).await.len(),
+            1 + VARIANT_SIZES.len()
+        );
+    }
+}
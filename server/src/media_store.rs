@@ -0,0 +1,429 @@
+//! Backends for storing the bytes of media files.
+//!
+//! Everything else about media (what it is, who uploaded it, what references it) lives in the
+//! database; a [`MediaStore`] only maps keys such as "<hash>" or "<hash>_<size>" (see
+//! [`crate::media::blob_key`]) to bytes. Media can be kept either in a directory on the local
+//! filesystem ([`LocalStore`]) or in a bucket of an S3-compatible object store such as AWS S3 or
+//! MinIO ([`S3Store`]), chosen with [`MediaStoreArgs`].
+
+use std::{fmt::Debug, io, path::PathBuf, sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+
+use color_eyre::eyre::{self, WrapErr};
+
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore};
+
+use rocket::{futures::TryStreamExt, tokio::fs};
+
+use tracing::info;
+
+/// Metadata about a stored object.
+#[derive(Debug, Clone)]
+pub struct ObjectStat {
+    pub key: String,
+
+    /// The size of the object in bytes.
+    pub size: u64,
+
+    /// When the object was last written.
+    pub modified: SystemTime,
+}
+
+/// A place to keep the bytes of media files, addressed by key.
+#[async_trait]
+pub trait MediaStore: Debug + Send + Sync {
+    /// Store the given bytes under the given key, replacing any object already stored there.
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> eyre::Result<()>;
+
+    /// Get the bytes stored under the given key, or `None` if there are none.
+    async fn get(&self, key: &str) -> eyre::Result<Option<Vec<u8>>>;
+
+    /// Delete the object stored under the given key. Deleting a missing object is not an error.
+    async fn delete(&self, key: &str) -> eyre::Result<()>;
+
+    /// Get metadata about the object stored under the given key, or `None` if there is none.
+    async fn stat(&self, key: &str) -> eyre::Result<Option<ObjectStat>>;
+
+    /// Get metadata about every object in the store.
+    async fn list(&self) -> eyre::Result<Vec<ObjectStat>>;
+}
+
+/// Media stored as files in a directory on the local filesystem, named by their keys.
+#[derive(Debug)]
+pub struct LocalStore {
+    dir: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+/// Map a "not found" error to `None`, so that missing objects aren't treated as failures.
+fn found<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(t) => Ok(Some(t)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[async_trait]
+impl MediaStore for LocalStore {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> eyre::Result<()> {
+        let path = self.dir.join(key);
+
+        fs::write(&path, bytes)
+            .await
+            .wrap_err_with(|| format!("failed to write media file {path:?}"))
+    }
+
+    async fn get(&self, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+        let path = self.dir.join(key);
+
+        found(fs::read(&path).await).wrap_err_with(|| format!("failed to read media file {path:?}"))
+    }
+
+    async fn delete(&self, key: &str) -> eyre::Result<()> {
+        let path = self.dir.join(key);
+
+        found(fs::remove_file(&path).await)
+            .wrap_err_with(|| format!("failed to delete media file {path:?}"))?;
+
+        Ok(())
+    }
+
+    async fn stat(&self, key: &str) -> eyre::Result<Option<ObjectStat>> {
+        let path = self.dir.join(key);
+
+        let metadata = found(fs::metadata(&path).await)
+            .wrap_err_with(|| format!("failed to stat media file {path:?}"))?;
+
+        let Some(metadata) = metadata.filter(|metadata| metadata.is_file()) else {
+            return Ok(None);
+        };
+
+        Ok(Some(ObjectStat {
+            key: key.to_owned(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        }))
+    }
+
+    async fn list(&self) -> eyre::Result<Vec<ObjectStat>> {
+        let mut entries = fs::read_dir(&self.dir)
+            .await
+            .wrap_err_with(|| format!("failed to read media directory {:?}", self.dir))?;
+
+        let mut objects = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+
+            // files whose names aren't UTF-8 can't have been stored under a key
+            let (true, Ok(key)) = (metadata.is_file(), entry.file_name().into_string()) else {
+                continue;
+            };
+
+            objects.push(ObjectStat {
+                key,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+
+        Ok(objects)
+    }
+}
+
+/// Media stored as objects in a bucket of an S3-compatible object store, named by their keys.
+#[derive(Debug)]
+pub struct S3Store {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl S3Store {
+    /// Connect to the given bucket. If `endpoint` is `None`, the bucket is on AWS; otherwise, it is
+    /// on the S3-compatible service at that URL (e.g. "http://127.0.0.1:9000" for a local MinIO
+    /// server). Credentials and the region are read from the standard `AWS_*` environment
+    /// variables.
+    pub fn new(bucket: &str, endpoint: Option<&str>) -> eyre::Result<Self> {
+        let store = Self::builder(bucket, endpoint)
+            .build()
+            .wrap_err_with(|| format!("failed to configure S3 bucket {bucket:?}"))?;
+
+        Ok(Self {
+            store: Arc::new(store),
+        })
+    }
+
+    /// The configuration of the store made by [`Self::new`].
+    fn builder(bucket: &str, endpoint: Option<&str>) -> AmazonS3Builder {
+        let builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+
+        match endpoint {
+            Some(endpoint) => builder
+                .with_endpoint(endpoint)
+                .with_allow_http(endpoint.starts_with("http://")),
+            None => builder,
+        }
+    }
+
+    /// A store which keeps objects in memory, behaving like an empty bucket. Used for testing.
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            store: Arc::new(object_store::memory::InMemory::new()),
+        }
+    }
+}
+
+/// Map a "not found" error to `None`, so that missing objects aren't treated as failures.
+fn object_found<T>(result: object_store::Result<T>) -> object_store::Result<Option<T>> {
+    match result {
+        Ok(t) => Ok(Some(t)),
+        Err(object_store::Error::NotFound { .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[async_trait]
+impl MediaStore for S3Store {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> eyre::Result<()> {
+        self.store
+            .put(&ObjectPath::from(key), bytes.into())
+            .await
+            .wrap_err_with(|| format!("failed to put media object {key:?}"))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> eyre::Result<Option<Vec<u8>>> {
+        let Some(result) = object_found(self.store.get(&ObjectPath::from(key)).await)
+            .wrap_err_with(|| format!("failed to get media object {key:?}"))?
+        else {
+            return Ok(None);
+        };
+
+        let bytes = result
+            .bytes()
+            .await
+            .wrap_err_with(|| format!("failed to read media object {key:?}"))?;
+
+        Ok(Some(bytes.into()))
+    }
+
+    async fn delete(&self, key: &str) -> eyre::Result<()> {
+        object_found(self.store.delete(&ObjectPath::from(key)).await)
+            .wrap_err_with(|| format!("failed to delete media object {key:?}"))?;
+
+        Ok(())
+    }
+
+    async fn stat(&self, key: &str) -> eyre::Result<Option<ObjectStat>> {
+        let meta = object_found(self.store.head(&ObjectPath::from(key)).await)
+            .wrap_err_with(|| format!("failed to stat media object {key:?}"))?;
+
+        Ok(meta.map(|meta| ObjectStat {
+            key: key.to_owned(),
+            size: meta.size as u64,
+            modified: meta.last_modified.into(),
+        }))
+    }
+
+    async fn list(&self) -> eyre::Result<Vec<ObjectStat>> {
+        self.store
+            .list(None)
+            .map_ok(|meta| ObjectStat {
+                key: meta.location.to_string(),
+                size: meta.size as u64,
+                modified: meta.last_modified.into(),
+            })
+            .try_collect()
+            .await
+            .wrap_err("failed to list media objects")
+    }
+}
+
+/// Command-line arguments choosing where media is stored.
+#[derive(clap::Args, Debug)]
+pub struct MediaStoreArgs {
+    /// Where to look for and store media such as profile pictures and course thumbnails, if
+    /// storing media on the local filesystem.
+    #[arg(long, required_unless_present = "media_s3_bucket")]
+    pub media_dir: Option<PathBuf>,
+
+    /// Store media in this bucket of an S3-compatible object store instead of in a local
+    /// directory. Credentials and the region are read from the standard `AWS_*` environment
+    /// variables.
+    #[arg(long, conflicts_with = "media_dir")]
+    pub media_s3_bucket: Option<String>,
+
+    /// The URL of the S3-compatible service hosting `--media-s3-bucket`, if not AWS (e.g.
+    /// "http://127.0.0.1:9000" for a local MinIO server).
+    #[arg(long, requires = "media_s3_bucket")]
+    pub media_s3_endpoint: Option<String>,
+}
+
+impl MediaStoreArgs {
+    /// Open the media store described by these arguments.
+    pub fn open(&self) -> eyre::Result<Arc<dyn MediaStore>> {
+        match (&self.media_dir, &self.media_s3_bucket) {
+            (_, Some(bucket)) => {
+                info!("storing media in S3 bucket {bucket:?}");
+
+                Ok(Arc::new(S3Store::new(
+                    bucket,
+                    self.media_s3_endpoint.as_deref(),
+                )?))
+            }
+            (Some(dir), None) => {
+                info!("storing media in {dir:?}");
+
+                crate::ensure_dir_exists(dir)?;
+
+                Ok(Arc::new(LocalStore::new(dir.clone())))
+            }
+            (None, None) => eyre::bail!("no media store given"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    /// The keys of every object in the store, in order.
+    async fn keys(store: &dyn MediaStore) -> Vec<String> {
+        let mut keys: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+
+        keys.sort();
+        keys
+    }
+
+    /// Check that the given empty store behaves the way that [`MediaStore`] describes.
+    async fn check_conformance(store: &dyn MediaStore) {
+        let hash = "511e74222cb8ec03b44257077627981b9f9ad275dc1b51bd4707c7e8a572a92a";
+        let variant = format!("{hash}_64");
+
+        assert!(keys(store).await.is_empty());
+        assert_eq!(store.get(hash).await.unwrap(), None);
+        assert!(store.stat(hash).await.unwrap().is_none());
+        store.delete(hash).await.unwrap();
+
+        store.put(hash, b"original".to_vec()).await.unwrap();
+        store.put(&variant, b"variant".to_vec()).await.unwrap();
+
+        assert_eq!(
+            store.get(hash).await.unwrap().as_deref(),
+            Some(&b"original"[..])
+        );
+        assert_eq!(keys(store).await, [hash, variant.as_str()]);
+
+        let stat = store.stat(hash).await.unwrap().expect("object is missing");
+        assert_eq!(stat.key, hash);
+        assert_eq!(stat.size, 8);
+
+        let age = SystemTime::now()
+            .duration_since(stat.modified)
+            .unwrap_or_default();
+        assert!(age < Duration::from_secs(60), "{age:?}");
+
+        store.put(hash, b"replaced!".to_vec()).await.unwrap();
+
+        assert_eq!(
+            store.get(hash).await.unwrap().as_deref(),
+            Some(&b"replaced!"[..])
+        );
+        assert_eq!(store.stat(hash).await.unwrap().unwrap().size, 9);
+        assert_eq!(keys(store).await, [hash, variant.as_str()]);
+
+        store.delete(hash).await.unwrap();
+
+        assert_eq!(store.get(hash).await.unwrap(), None);
+        assert!(store.stat(hash).await.unwrap().is_none());
+        assert_eq!(keys(store).await, [variant.as_str()]);
+        assert_eq!(
+            store.get(&variant).await.unwrap().as_deref(),
+            Some(&b"variant"[..])
+        );
+    }
+
+    #[rocket::async_test]
+    async fn local_store_conforms() {
+        let dir = TempDir::new().unwrap();
+
+        check_conformance(&LocalStore::new(dir.path().to_owned())).await;
+    }
+
+    #[rocket::async_test]
+    async fn s3_store_conforms() {
+        check_conformance(&S3Store::in_memory()).await;
+    }
+
+    /// Run with `--ignored` to check against a real bucket (e.g. on a local MinIO server), which
+    /// must be empty. The bucket is named by `TEST_S3_BUCKET`, and the URL of the service hosting
+    /// it by `TEST_S3_ENDPOINT` if it is not on AWS. Credentials are read from the `AWS_*`
+    /// environment variables.
+    #[rocket::async_test]
+    #[ignore = "needs an S3 bucket"]
+    async fn s3_bucket_conforms() {
+        let bucket = std::env::var("TEST_S3_BUCKET").expect("TEST_S3_BUCKET is not set");
+        let endpoint = std::env::var("TEST_S3_ENDPOINT").ok();
+
+        let store = S3Store::new(&bucket, endpoint.as_deref()).unwrap();
+
+        check_conformance(&store).await;
+
+        for key in keys(&store).await {
+            store.delete(&key).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn configures_s3_endpoint() {
+        use object_store::{aws::AmazonS3ConfigKey, ClientConfigKey};
+
+        let config = |bucket, endpoint| {
+            let builder = S3Store::builder(bucket, endpoint);
+
+            [
+                AmazonS3ConfigKey::Bucket,
+                AmazonS3ConfigKey::Endpoint,
+                AmazonS3ConfigKey::Client(ClientConfigKey::AllowHttp),
+            ]
+            .map(|key| builder.get_config_value(&key))
+        };
+
+        let [bucket, _, allow_http] = config("media", None);
+        assert_eq!(bucket.as_deref(), Some("media"));
+        assert_ne!(allow_http.as_deref(), Some("true"));
+
+        assert_eq!(
+            config("media", Some("http://127.0.0.1:9000")),
+            [
+                Some("media".to_owned()),
+                Some("http://127.0.0.1:9000".to_owned()),
+                Some("true".to_owned()),
+            ]
+        );
+
+        let [_, endpoint, allow_http] = config("media", Some("https://minio.example.com"));
+        assert_eq!(endpoint.as_deref(), Some("https://minio.example.com"));
+        assert_eq!(allow_http.as_deref(), Some("false"));
+
+        S3Store::new("media", Some("http://127.0.0.1:9000")).unwrap();
+    }
+}
@@ -1,6 +1,17 @@
 //! Defines API route handlers via Rocket
 
-use crate::structs::{ActivityData, Name, Stats, USId};
+use crate::{
+    auth::{self, AdminUser, AuthedUser},
+    courses,
+    db::{self, record_id},
+    media::{self, MediaError, MediaLimit},
+    search::{
+        self, AnyResult, AssignmentResult, CourseResult, Filters, MajorResult, Scored, SearchKind,
+        Searchable, Searcher, UniResult, UserResult,
+    },
+    stats,
+    structs::{ActivityData, Course, Name, Stats, USId},
+};
 
 use super::{err::LogMapErr, structs::User, State};
 
@@ -8,17 +19,22 @@ use std::str::FromStr;
 
 use color_eyre::eyre::WrapErr;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, TimeDelta, Utc};
 
-use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use rocket::{
+    data::Data,
+    form::{self, FromFormField, ValueField},
+    http::{ContentType, Header, Status},
+    request::FromParam,
+    response::{self, Responder, Response},
+    serde::json::Json,
+};
 
-use rocket::{http::Status, request::FromParam};
-
-use surrealdb::{engine::remote::ws::Client, opt::QueryResult, Surreal};
+use surrealdb::{engine::any::Any, method::Query, opt::QueryResult, RecordId, Surreal};
 
 use serde::de::DeserializeOwned;
 
-use tracing::{debug, instrument};
+use tracing::{debug, error, info, instrument};
 
 use ulid::Ulid;
 
@@ -27,7 +43,7 @@ use ulid::Ulid;
 /// characters and they're lexicographically sortable, and plus ULIDs are just more aesthetically
 /// pleasing.
 #[derive(Debug)]
-struct UlidParam(Ulid);
+pub struct UlidParam(Ulid);
 
 impl<'a> FromParam<'a> for UlidParam {
     type Error = <Ulid as FromStr>::Err;
@@ -37,165 +53,470 @@ impl<'a> FromParam<'a> for UlidParam {
     }
 }
 
-/// Wrapper which guarantees query safety when being parsed from an URL. Specifically, its
-/// implementation of FromParam validates that it consists only of ASCII alphanumeric and whitespace
-/// characters (e.g. no quotes or backslashes that could escape from a string).
-#[derive(Debug)]
-struct CleanStr<'a>(&'a str);
+/// Wrapper to implement parsing of optional query params. Rocket quietly parses an `Option<T>`
+/// param as `None` if it is present but invalid, which would e.g. make a search ignore a mistyped
+/// filter; this fails the request with 422 instead.
+#[derive(Debug, Clone, Copy)]
+pub struct OptionalParam<T>(Option<T>);
+
+impl<'v, T> FromFormField<'v> for OptionalParam<T>
+where
+    T: FromStr + Send,
+{
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let value = field
+            .value
+            .parse()
+            .map_err(|_| form::Error::validation("invalid value"))?;
 
-impl<'a> FromParam<'a> for CleanStr<'a> {
-    type Error = ();
+        Ok(OptionalParam(Some(value)))
+    }
 
-    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
-        if !param
-            .chars()
-            .all(|c| c.is_ascii_alphanumeric() || c.is_ascii_whitespace())
-        {
-            return Err(());
+    fn default() -> Option<Self> {
+        Some(OptionalParam(None))
+    }
+}
+
+/// Number of items in a [`Page`] if the `limit` query parameter is not given.
+const DEFAULT_PAGE_LIMIT: usize = 50;
+
+/// Maximum value of the `limit` query parameter.
+const MAX_PAGE_LIMIT: usize = 200;
+
+/// Query parameters of list routes, which return their items one [`Page`] at a time. Items are
+/// sorted by ID, and the cursor is the ID of the last item of the previous page; ULIDs are
+/// sortable, so this keeps pages consistent even as items are added. Search routes are sorted by
+/// relevance instead, and their cursor is a [`SearchCursor`]; activity routes are sorted by time,
+/// and their cursor is an [`ActivityCursor`]. If `limit` is out of range or `cursor` is invalid,
+/// returns 422.
+#[derive(FromForm, Debug)]
+pub struct PageParams<C: FromStr + Send = Ulid> {
+    /// Maximum number of items to return, from 1 to [`MAX_PAGE_LIMIT`].
+    #[field(default = DEFAULT_PAGE_LIMIT)]
+    #[field(validate = range(1..=MAX_PAGE_LIMIT as isize))]
+    limit: usize,
+
+    /// The `next_cursor` of the previous page, or none to get the first page.
+    cursor: OptionalParam<C>,
+}
+
+impl PageParams {
+    /// The cursor as an ID in the given table, for binding to `$cursor` in a query.
+    fn cursor(&self, table: &str) -> Option<RecordId> {
+        self.cursor.0.map(|cursor| record_id(table, cursor))
+    }
+
+    /// The given condition on `$cursor`, to be `AND`ed onto a `WHERE` clause, if there is a
+    /// cursor. SurrealDB doesn't use indexes for a clause like `in == $user AND ($cursor == NONE OR
+    /// out > $cursor)`, so the condition is left out of the query entirely if there is no cursor.
+    fn cursor_condition(&self, condition: &str) -> String {
+        match self.cursor.0 {
+            Some(_) => format!("AND {condition}"),
+            None => String::new(),
         }
+    }
+}
+
+impl<C: FromStr + Send> PageParams<C> {
+    /// The number of items to fetch in order to fill a page, for binding to `$limit` in a query.
+    /// This is one more than the limit so that [`Page::new`] can tell whether there is a next page.
+    fn fetch_limit(&self) -> usize {
+        self.limit + 1
+    }
+}
+
+/// Cursor of search routes, written as "<relevance>_<id>": the relevance and ID of the last result
+/// of the previous page. Searches are ranked in memory, so each page reruns the search; the next
+/// page then has the results which rank after the cursor's position (lower relevance, or equal
+/// relevance and a greater ID), so that it isn't cut short if the cursor's own result has since
+/// changed relevance or been deleted. Results whose relevance changes between pages may still be
+/// repeated or skipped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchCursor {
+    relevance: f64,
+    id: Ulid,
+}
 
-        Ok(Self(param))
+impl SearchCursor {
+    fn of<T>(scored: &Scored<T>, get_id: impl Fn(&T) -> USId) -> Self {
+        Self {
+            relevance: scored.relevance,
+            id: get_id(&scored.result).ulid(),
+        }
+    }
+
+    /// Whether a result with the given cursor ranks after this one (see
+    /// [`search::sort_by_relevance`]).
+    fn precedes(&self, other: &SearchCursor) -> bool {
+        other
+            .relevance
+            .total_cmp(&self.relevance)
+            .reverse()
+            .then_with(|| other.id.cmp(&self.id))
+            .is_gt()
     }
 }
 
-/// Helper function for doing a query on the database and transforming errors to log messages +
-/// HTTP 500 status.
-async fn single_query<T>(db: &Surreal<Client>, query: &str) -> Result<T, Status>
+impl FromStr for SearchCursor {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (relevance, id) = s
+            .split_once('_')
+            .ok_or_else(|| color_eyre::eyre::eyre!("search cursor has no '_'"))?;
+
+        Ok(Self {
+            relevance: relevance.parse()?,
+            id: id.parse()?,
+        })
+    }
+}
+
+/// Displays as "<relevance>_<id>". Floats display with just enough digits to parse back to the
+/// same value, so the cursor round-trips exactly.
+impl std::fmt::Display for SearchCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.relevance, self.id)
+    }
+}
+
+impl serde::Serialize for SearchCursor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl PageParams<SearchCursor> {
+    /// Whether the given search result ranks after the cursor, if there is one.
+    fn follows_cursor<T>(&self, scored: &Scored<T>, get_id: impl Fn(&T) -> USId) -> bool {
+        self.cursor
+            .0
+            .is_none_or(|cursor| cursor.precedes(&SearchCursor::of(scored, get_id)))
+    }
+
+    /// Whether the given search results, sorted by relevance, fill this page, so that the search
+    /// needn't look for results with typos (see [`search::search`]).
+    fn is_filled_by<T>(&self, results: &[Scored<T>], get_id: impl Fn(&T) -> USId + Copy) -> bool {
+        results
+            .iter()
+            .filter(|scored| self.follows_cursor(scored, get_id))
+            .count()
+            >= self.fetch_limit()
+    }
+}
+
+/// Cursor of activity routes, written as "<time>_<id>": the RFC 3339 time and the ID of the last
+/// activity of the previous page. Activity is sorted in decreasing order of time, and activities
+/// registered at the same time are sorted in decreasing order of ID.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActivityCursor {
+    time: DateTime<Utc>,
+    id: Ulid,
+}
+
+impl FromStr for ActivityCursor {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (time, id) = s
+            .split_once('_')
+            .ok_or_else(|| color_eyre::eyre::eyre!("activity cursor has no '_'"))?;
+
+        Ok(Self {
+            time: DateTime::parse_from_rfc3339(time)?.with_timezone(&Utc),
+            id: id.parse()?,
+        })
+    }
+}
+
+/// Displays as "<time>_<id>". The time is written with a "Z" rather than a "+00:00" offset, which
+/// would have to be escaped in a URL.
+impl std::fmt::Display for ActivityCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = self.time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        write!(f, "{time}_{}", self.id)
+    }
+}
+
+impl serde::Serialize for ActivityCursor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl PageParams<ActivityCursor> {
+    /// Query for a page of activity, selecting the given fields of the activities which meet the
+    /// given condition. The cursor is bound to `$time` and `$cursor` by [`Self::bind_cursor`].
+    /// SurrealDB doesn't use indexes for a clause like `user == $user AND (time < $time OR id <
+    /// $cursor)`, so activity up to the cursor's time is found first and the rest of the cursor is
+    /// applied to that.
+    fn activity_query(&self, fields: &str, condition: &str) -> String {
+        match self.cursor.0 {
+            Some(_) => format!(
+                "SELECT * FROM (
+                    SELECT {fields} FROM activity WHERE {condition} AND time <= <datetime>$time
+                )
+                WHERE time < <datetime>$time OR id < $cursor
+                ORDER BY time DESC, id DESC
+                LIMIT $limit"
+            ),
+            None => format!(
+                "SELECT {fields} FROM activity
+                WHERE {condition}
+                ORDER BY time DESC, id DESC
+                LIMIT $limit"
+            ),
+        }
+    }
+
+    /// Bind the cursor and limit of the query made by [`Self::activity_query`].
+    fn bind_cursor<'q>(&self, query: Query<'q, Any>) -> Query<'q, Any> {
+        query
+            .bind(("time", self.cursor.0.map(|cursor| cursor.time)))
+            .bind((
+                "cursor",
+                self.cursor.0.map(|cursor| record_id("activity", cursor.id)),
+            ))
+            .bind(("limit", self.fetch_limit()))
+    }
+}
+
+/// Response body of list routes: a page of items along with the cursor to pass to get the next
+/// page (see [`PageParams`]).
+#[derive(serde::Serialize, Debug)]
+struct Page<T, C = USId> {
+    items: Vec<T>,
+
+    /// Cursor of the last item, if there are more items after it.
+    next_cursor: Option<C>,
+}
+
+impl<T, C> Page<T, C> {
+    /// Make a page from the items following the cursor, of which there should be up to
+    /// [`PageParams::fetch_limit`].
+    fn new<P: FromStr + Send>(
+        mut items: Vec<T>,
+        params: &PageParams<P>,
+        get_cursor: impl Fn(&T) -> C,
+    ) -> Self {
+        let next_cursor = if items.len() > params.limit {
+            items.truncate(params.limit);
+            items.last().map(get_cursor)
+        } else {
+            None
+        };
+
+        Self { items, next_cursor }
+    }
+
+    fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U, C> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+impl<T> Page<Scored<T>, SearchCursor> {
+    /// Make a page from all of the results of a search, sorted by relevance, by skipping those up
+    /// to the cursor (see [`SearchCursor`]).
+    fn from_search(
+        results: Vec<Scored<T>>,
+        params: &PageParams<SearchCursor>,
+        get_id: impl Fn(&T) -> USId + Copy,
+    ) -> Self {
+        let results = results
+            .into_iter()
+            .filter(|scored| params.follows_cursor(scored, get_id))
+            .take(params.fetch_limit())
+            .collect();
+
+        Self::new(results, params, |scored| SearchCursor::of(scored, get_id))
+    }
+}
+
+/// Helper function for looking up the caller of a search route, if they are logged in, so that
+/// results related to them can be boosted (see [`search::SearchBoosts`]).
+async fn searcher(
+    state: &State<Any>,
+    authed: Option<AuthedUser>,
+) -> Result<Option<Searcher>, Status> {
+    let Some(authed) = authed else {
+        return Ok(None);
+    };
+
+    search::searcher(&state.db, authed.id, state.search_boosts)
+        .await
+        .log_map_err(|_| Status::InternalServerError)
+}
+
+/// Helper function for searching records of one kind (see [`search::search`]) and returning a page
+/// of the results, without their relevance.
+async fn search_page<T: Searchable + serde::Serialize>(
+    db: &Surreal<Any>,
+    search_str: &str,
+    filters: &Filters,
+    searcher: Option<&Searcher>,
+    page: &PageParams<SearchCursor>,
+) -> Result<String, Status> {
+    let enough = |results: &[Scored<T>]| page.is_filled_by(results, T::id);
+
+    let results = search::search::<T>(db, search_str, filters, searcher, enough)
+        .await
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    to_json(&Page::from_search(results, page, T::id).map(|scored| scored.result))
+}
+
+/// Helper function for running a query (with its parameters bound) on the database and transforming
+/// errors to log messages + HTTP 500 status.
+async fn single_query<T>(query: Query<'_, Any>) -> Result<T, Status>
 where
     usize: QueryResult<T>,
     T: DeserializeOwned,
 {
-    Ok(db
-        .query(query)
+    query
         .await
-        .and_then(|mut resp| resp.take(0))
-        .log_map_err(|_| Status::InternalServerError)?)
+        .log_map_err(|_| Status::InternalServerError)?
+        .take(0)
+        .log_map_err(|_| Status::InternalServerError)
 }
 
-/// Helper function for performing fuzzy search on a particular column or columns of a table in the
-/// database.
-async fn search_table<const N: usize, T>(
-    db: &Surreal<Client>,
-    query: &str,
-    search: &str,
-    get_keys: impl Fn(&T) -> [&str; N],
-) -> Result<Vec<T>, Status>
+/// Helper function for serializing a response body to JSON and transforming errors to log messages
+/// + HTTP 500 status.
+fn to_json<T>(value: &T) -> Result<String, Status>
 where
-    T: serde::de::DeserializeOwned,
+    T: serde::Serialize,
 {
-    let mut results: Vec<T> = single_query(db, query).await?;
-
-    let matcher = SkimMatcherV2::default();
-
-    results.sort_by_cached_key(|result| {
-        std::cmp::Reverse(
-            get_keys(result)
-                .into_iter()
-                .map(|k| matcher.fuzzy_match(k, search).unwrap_or(i64::MIN))
-                .max()
-                .unwrap_or(i64::MIN),
-        )
-    });
-
-    Ok(results)
+    serde_json::to_string(value)
+        .wrap_err("failed to serialize response")
+        .log_map_err(|_| Status::InternalServerError)
 }
 
-// -------------------------------------------------------------------------------------------------
-// SAFETY: you will see me interpolate captured URL fragments into query strings in the following
-// route handlers. As long as the type of the URL fragment has a restrictive syntax (e.g. a ULID),
-// this does not allow for query injection as Rocket parses the fragments before the handler
-// function is even called, and an error at that point will result in a 404.
-//
-// When interpolating general strings, I use `CleanStr`, which automatically validates that it is
-// clean during the url parsing phase.
-// -------------------------------------------------------------------------------------------------
-
 /// GET "/api/user/<id>": data of user with a given user ID. If a user with the given ID does
 /// not exist, returns 404.
 #[instrument(skip(state))]
 #[get("/user/<id_param>", rank = 1)]
 pub async fn user(
-    state: &rocket::State<State<Client>>,
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
 ) -> Result<String, Status> {
-    let query = format!("SELECT * FROM ONLY user:`{id}`");
+    let query = state
+        .db
+        .query("SELECT * FROM ONLY $user")
+        .bind(("user", record_id("user", id)));
 
-    let user = single_query::<Option<User>>(&state.db, &query)
+    let user = single_query::<Option<User>>(query)
         .await?
         .ok_or(Status::NotFound)?;
 
-    Ok(serde_json::to_string(&user)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    to_json(&user)
 }
 
-/// GET "/api/user/<id>/following": list of IDs of users that the given user is following. If a user
-/// with the given id does not exist, returns 404.
+/// GET "/api/user/<id>/following?<limit>&<cursor>": page of IDs of users that the given user is
+/// following, sorted by ID (see [`PageParams`]).
 #[instrument(skip(state))]
-#[get("/user/<id_param>/following")]
+#[get("/user/<id_param>/following?<page..>")]
 pub async fn user_following(
-    state: &rocket::State<State<Client>>,
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
+    page: PageParams,
 ) -> Result<String, Status> {
-    let query = format!("SELECT VALUE out FROM follows WHERE in=user:`{id}`");
-
-    let user_ids: Vec<USId> = single_query(&state.db, &query).await?;
-
-    Ok(serde_json::to_string(&user_ids)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    let query = state
+        .db
+        .query(format!(
+            "SELECT VALUE out FROM follows
+            WHERE in == $user {}
+            ORDER BY out
+            LIMIT $limit",
+            page.cursor_condition("out > $cursor")
+        ))
+        .bind(("user", record_id("user", id)))
+        .bind(("cursor", page.cursor("user")))
+        .bind(("limit", page.fetch_limit()));
+
+    let user_ids: Vec<USId> = single_query(query).await?;
+
+    to_json(&Page::new(user_ids, &page, |id| *id))
 }
 
-/// GET "/api/user/<id>/followers": list of IDs of users that follow the given user. If a user with
-/// the given id does not exist, returns 404.
+/// GET "/api/user/<id>/followers?<limit>&<cursor>": page of IDs of users that follow the given
+/// user, sorted by ID (see [`PageParams`]).
 #[instrument(skip(state))]
-#[get("/user/<id_param>/followers")]
+#[get("/user/<id_param>/followers?<page..>")]
 pub async fn user_followers(
-    state: &rocket::State<State<Client>>,
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
+    page: PageParams,
 ) -> Result<String, Status> {
-    let query = format!("SELECT VALUE in FROM follows WHERE out=user:`{id}`");
-
-    let user_ids: Vec<USId> = single_query(&state.db, &query).await?;
-
-    Ok(serde_json::to_string(&user_ids)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    let query = state
+        .db
+        .query(format!(
+            "SELECT VALUE in FROM follows
+            WHERE out == $user {}
+            ORDER BY in
+            LIMIT $limit",
+            page.cursor_condition("in > $cursor")
+        ))
+        .bind(("user", record_id("user", id)))
+        .bind(("cursor", page.cursor("user")))
+        .bind(("limit", page.fetch_limit()));
+
+    let user_ids: Vec<USId> = single_query(query).await?;
+
+    to_json(&Page::new(user_ids, &page, |id| *id))
 }
 
-/// GET "/api/user/<id>/courses": list of IDs of courses that the given user is taking. If a user
-/// with the given id does not exist, returns 404.
+/// GET "/api/user/<id>/courses?<limit>&<cursor>": page of IDs of courses that the given user is
+/// taking, sorted by ID (see [`PageParams`]).
 #[instrument(skip(state))]
-#[get("/user/<id_param>/courses")]
+#[get("/user/<id_param>/courses?<page..>")]
 pub async fn user_courses(
-    state: &rocket::State<State<Client>>,
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
+    page: PageParams,
 ) -> Result<String, Status> {
-    let query = format!("SELECT VALUE out FROM takes_course WHERE in=user:`{id}`");
-
-    let user_ids: Vec<USId> = single_query(&state.db, &query).await?;
-
-    Ok(serde_json::to_string(&user_ids)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    let query = state
+        .db
+        .query(format!(
+            "SELECT VALUE out FROM takes_course
+            WHERE in == $user {}
+            ORDER BY out
+            LIMIT $limit",
+            page.cursor_condition("out > $cursor")
+        ))
+        .bind(("user", record_id("user", id)))
+        .bind(("cursor", page.cursor("course")))
+        .bind(("limit", page.fetch_limit()));
+
+    let course_ids: Vec<USId> = single_query(query).await?;
+
+    to_json(&Page::new(course_ids, &page, |id| *id))
 }
 
 /// GET "/api/user/<id>/stats": statistics of the given user related to their activity.
 #[instrument(skip(state))]
 #[get("/user/<id_param>/stats", rank = 3)]
 pub async fn user_stats(
-    state: &rocket::State<State<Client>>,
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
 ) -> Result<String, Status> {
-    let query = format!("SELECT VALUE stats FROM ONLY user:`{id}`");
+    let query = state
+        .db
+        .query("SELECT VALUE stats FROM ONLY $user")
+        .bind(("user", record_id("user", id)));
 
-    let stats = single_query::<Option<Stats>>(&state.db, &query)
+    let stats = single_query::<Option<Stats>>(query)
         .await?
         .ok_or(Status::NotFound)?;
 
-    Ok(serde_json::to_string(&stats)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    to_json(&stats)
 }
 
 /// GET "/api/user/<id>/assignment_statuses": lists of IDs of assignments planned, in progress, and
@@ -203,7 +524,7 @@ pub async fn user_stats(
 #[instrument(skip(state))]
 #[get("/user/<id_param>/assignment_statuses", rank = 3)]
 pub async fn user_assignment_statuses(
-    state: &rocket::State<State<Client>>,
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
 ) -> Result<String, Status> {
     #[derive(serde::Serialize, serde::Deserialize)]
@@ -213,206 +534,1275 @@ pub async fn user_assignment_statuses(
         assignments_completed: Vec<USId>,
     }
 
-    let query = format!(
-        "SELECT
-            assignments_planning,
-            assignments_in_progress,
-            assignments_completed
-        FROM ONLY user:`{id}`"
-    );
+    let query = state
+        .db
+        .query(
+            "SELECT
+                assignments_planning,
+                assignments_in_progress,
+                assignments_completed
+            FROM ONLY $user",
+        )
+        .bind(("user", record_id("user", id)));
 
-    let statuses = single_query::<Option<AssignmentStatuses>>(&state.db, &query)
+    let statuses = single_query::<Option<AssignmentStatuses>>(query)
         .await?
         .ok_or(Status::NotFound)?;
 
-    Ok(serde_json::to_string(&statuses)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    to_json(&statuses)
 }
 
-/// GET "/api/uni/<id>/students": list of IDs of users who attend the given university. If
-/// the given university ID does not exist, returns 404.
+/// GET "/api/uni/<id>/students?<limit>&<cursor>": page of IDs of users who attend the given
+/// university, sorted by ID (see [`PageParams`]).
 #[instrument(skip(state))]
-#[get("/uni/<id_param>/students")]
+#[get("/uni/<id_param>/students?<page..>")]
 pub async fn uni_students(
-    state: &rocket::State<State<Client>>,
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
+    page: PageParams,
+) -> Result<String, Status> {
+    let query = state
+        .db
+        .query(format!(
+            "SELECT VALUE id FROM user
+            WHERE university == $university {}
+            ORDER BY id
+            LIMIT $limit",
+            page.cursor_condition("id > $cursor")
+        ))
+        .bind(("university", record_id("university", id)))
+        .bind(("cursor", page.cursor("user")))
+        .bind(("limit", page.fetch_limit()));
+
+    let user_ids: Vec<USId> = single_query(query).await?;
+
+    to_json(&Page::new(user_ids, &page, |id| *id))
+}
+
+/// GET "/api/course/search/<search>?<university>&<limit>&<cursor>": page of courses whose names or
+/// codes match the given search string, sorted in order of search relevance (see [`SearchCursor`]).
+/// If `university` is given, only finds courses offered there, and if it is invalid, returns 422.
+/// If the caller is logged in, courses at their university are boosted (see
+/// [`search::SearchBoosts`]).
+#[instrument(skip(state))]
+#[get("/course/search/<search>?<university>&<page..>")]
+pub async fn course_search(
+    state: &rocket::State<State<Any>>,
+    search: &str,
+    university: OptionalParam<Ulid>,
+    page: PageParams<SearchCursor>,
+    authed: Option<AuthedUser>,
 ) -> Result<String, Status> {
-    let query = format!("SELECT VALUE id FROM user WHERE university == university:`{id}`");
+    let filters = Filters {
+        university: university.0,
+        ..Filters::default()
+    };
 
-    let user_ids: Vec<USId> = single_query(&state.db, &query).await?;
+    let searcher = searcher(state, authed).await?;
 
-    Ok(serde_json::to_string(&user_ids)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    search_page::<CourseResult>(&state.db, search, &filters, searcher.as_ref(), &page).await
 }
 
-/// GET "/api/course/search/<search>": list of courses whose names match the given search string,
-/// sorted in order of search relevance.
+/// GET "/api/assignment/search/<search>?<limit>&<cursor>": page of assignments whose names match
+/// the given search string, sorted in order of search relevance (see [`SearchCursor`]).
 #[instrument(skip(state))]
-#[get("/course/search/<search_param>")]
-pub async fn course_search(
-    state: &rocket::State<State<Client>>,
-    search_param @ CleanStr(search): CleanStr<'_>,
+#[get("/assignment/search/<search>?<page..>")]
+pub async fn assignment_search(
+    state: &rocket::State<State<Any>>,
+    search: &str,
+    page: PageParams<SearchCursor>,
 ) -> Result<String, Status> {
-    #[derive(serde::Serialize, serde::Deserialize)]
-    struct SearchResult {
-        id: USId,
-        name: String,
-        code: String,
-    }
+    search_page::<AssignmentResult>(&state.db, search, &Filters::default(), None, &page).await
+}
 
-    let query =
-        format!("SELECT id, name, code FROM course WHERE name ~ '{search}' OR code ~ '{search}'");
+/// GET "/api/user/search/<search>?<university>&<major>&<grad_year>&<course>&<limit>&<cursor>": page
+/// of users whose usernames or names match the given search string, sorted in order of search
+/// relevance (see [`SearchCursor`]). If `university`, `major`, `grad_year`, or `course` is given,
+/// only finds users who attend that university, have that major, graduate that year, and take that
+/// course (see [`search::Filters`]). If any of them is invalid, returns 422. If the caller is
+/// logged in, users at their university, in their courses, or in their follow network are boosted
+/// (see [`search::SearchBoosts`]).
+#[instrument(skip(state))]
+#[get(
+    "/user/search/<search>?<university>&<major>&<grad_year>&<course>&<page..>",
+    rank = 2
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn user_search(
+    state: &rocket::State<State<Any>>,
+    search: &str,
+    university: OptionalParam<Ulid>,
+    major: OptionalParam<Ulid>,
+    grad_year: OptionalParam<i32>,
+    course: OptionalParam<Ulid>,
+    page: PageParams<SearchCursor>,
+    authed: Option<AuthedUser>,
+) -> Result<String, Status> {
+    let filters = Filters {
+        university: university.0,
+        major: major.0,
+        grad_year: grad_year.0,
+        course: course.0,
+    };
 
-    let search_results = search_table::<2, SearchResult>(&state.db, &query, search, |course| {
-        [&course.name, &course.code]
-    })
-    .await?;
+    let searcher = searcher(state, authed).await?;
 
-    Ok(serde_json::to_string(&search_results)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    search_page::<UserResult>(&state.db, search, &filters, searcher.as_ref(), &page).await
 }
 
-/// GET "/api/assignment/search/<search>": list of assignments whose names match the given search
-/// string, sorted in order of search relevance.
+/// GET "/api/uni/search/<search>?<limit>&<cursor>": page of universities whose names match the
+/// given search string, sorted in order of search relevance (see [`SearchCursor`]).
 #[instrument(skip(state))]
-#[get("/assignment/search/<search_param>")]
-pub async fn assignment_search(
-    state: &rocket::State<State<Client>>,
-    search_param @ CleanStr(search): CleanStr<'_>,
+#[get("/uni/search/<search>?<page..>", rank = 2)]
+pub async fn uni_search(
+    state: &rocket::State<State<Any>>,
+    search: &str,
+    page: PageParams<SearchCursor>,
+) -> Result<String, Status> {
+    search_page::<UniResult>(&state.db, search, &Filters::default(), None, &page).await
+}
+
+/// GET "/api/major/search/<search>?<limit>&<cursor>": page of majors whose names match the given
+/// search string, sorted in order of search relevance (see [`SearchCursor`]).
+#[instrument(skip(state))]
+#[get("/major/search/<search>?<page..>", rank = 2)]
+pub async fn major_search(
+    state: &rocket::State<State<Any>>,
+    search: &str,
+    page: PageParams<SearchCursor>,
+) -> Result<String, Status> {
+    search_page::<MajorResult>(&state.db, search, &Filters::default(), None, &page).await
+}
+
+/// GET "/api/search?<q>&<types>&<university>&<major>&<grad_year>&<course>&<limit>&<cursor>": page
+/// of records of any of the given types whose names match the search string `q`, sorted in
+/// decreasing order of relevance (see [`SearchCursor`]). `types` is a comma-separated list of
+/// "user", "course", "uni", "major", and "assignment", and defaults to all of them. Each result has
+/// a `type` field with its type, a `relevance` from 0 to 1, and the same fields as the results of
+/// that type's search route (e.g. "/api/user/search/<search>"), which also describe how results
+/// are filtered by `university`, `major`, `grad_year`, and `course`, and boosted if the caller is
+/// logged in; types which a filter doesn't apply to ignore it. If any of the types or filters are
+/// invalid, returns 422.
+#[instrument(skip(state))]
+#[get("/search?<q>&<types>&<university>&<major>&<grad_year>&<course>&<page..>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn unified_search(
+    state: &rocket::State<State<Any>>,
+    q: &str,
+    types: Option<&str>,
+    university: OptionalParam<Ulid>,
+    major: OptionalParam<Ulid>,
+    grad_year: OptionalParam<i32>,
+    course: OptionalParam<Ulid>,
+    page: PageParams<SearchCursor>,
+    authed: Option<AuthedUser>,
+) -> Result<String, Status> {
+    let kinds = match types {
+        Some(types) => {
+            let mut kinds = Vec::new();
+
+            for kind in types.split(',') {
+                let kind: SearchKind = kind.parse().log_map_err(|_| Status::UnprocessableEntity)?;
+
+                if !kinds.contains(&kind) {
+                    kinds.push(kind);
+                }
+            }
+
+            kinds
+        }
+        None => SearchKind::ALL.to_vec(),
+    };
+
+    let filters = Filters {
+        university: university.0,
+        major: major.0,
+        grad_year: grad_year.0,
+        course: course.0,
+    };
+
+    let searcher = searcher(state, authed).await?;
+
+    let enough = |results: &[Scored<AnyResult>]| page.is_filled_by(results, AnyResult::id);
+
+    let results = search::search_all(&state.db, q, &filters, searcher.as_ref(), &kinds, enough)
+        .await
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    to_json(&Page::from_search(results, &page, AnyResult::id))
+}
+
+/// GET "/api/user/<id>/activity?<limit>&<cursor>": page of activities registered by the given
+/// user, sorted in decreasing order of time (see [`ActivityCursor`]). Each activity includes the ID
+/// and code of its course and the ID and name of its assignment.
+#[instrument(skip(state))]
+#[get("/user/<id_param>/activity?<page..>", rank = 3)]
+pub async fn user_activity(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    page: PageParams<ActivityCursor>,
 ) -> Result<String, Status> {
     #[derive(serde::Serialize, serde::Deserialize)]
-    struct SearchResult {
+    struct CourseData {
+        id: USId,
+        code: String,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct AssignmentData {
         id: USId,
-        course: USId,
         name: String,
     }
 
-    let query = format!("SELECT id, course, name FROM assignment WHERE name ~ '{search}'");
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Activity {
+        id: USId,
+        time: DateTime<Utc>,
+        course: CourseData,
+        assignment: AssignmentData,
+        data: ActivityData,
+    }
+
+    let query = state
+        .db
+        .query(page.activity_query(
+            "id, time, course.id, course.code, assignment.id, assignment.name, data",
+            "user == $user",
+        ))
+        .bind(("user", record_id("user", id)));
 
-    let search_results =
-        search_table::<1, SearchResult>(&state.db, &query, search, |assignment| [&assignment.name])
-            .await?;
+    let activity: Vec<Activity> = single_query(page.bind_cursor(query)).await?;
 
-    Ok(serde_json::to_string(&search_results)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    to_json(&Page::new(activity, &page, |activity| ActivityCursor {
+        time: activity.time,
+        id: activity.id.ulid(),
+    }))
 }
 
-/// GET "/api/user/search/<search>": list of users whose names match the given search string,
-/// sorted in increasing order of fuzzy distance with the search string.
+/// GET "/api/user/<id>/feed?<limit>&<cursor>": page of activities registered by users that the
+/// given user is following, sorted in decreasing order of time like [`user_activity`]. Each
+/// activity includes the ID, username, and name of the user who registered it, the ID and code of
+/// its course, and the ID and name of its assignment.
 #[instrument(skip(state))]
-#[get("/user/search/<search_param>", rank = 2)]
-pub async fn user_search(
-    state: &rocket::State<State<Client>>,
-    search_param @ CleanStr(search): CleanStr<'_>,
+#[get("/user/<id_param>/feed?<page..>", rank = 3)]
+pub async fn user_feed(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    page: PageParams<ActivityCursor>,
 ) -> Result<String, Status> {
     #[derive(serde::Serialize, serde::Deserialize)]
-    struct SearchResult {
+    struct UserData {
         id: USId,
         username: String,
         name: Name,
     }
 
-    let query = format!(
-        "SELECT id, username, name FROM user 
-            WHERE username ~ '{search}' OR (name.first + ' ' + name.last) ~ '{search}'"
-    );
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct CourseData {
+        id: USId,
+        code: String,
+    }
 
-    debug!("query: `{query}`");
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct AssignmentData {
+        id: USId,
+        name: String,
+    }
 
-    let search_results = search_table::<3, SearchResult>(&state.db, &query, search, |user| {
-        [&user.username, &user.name.first, &user.name.last]
-    })
-    .await?;
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Activity {
+        id: USId,
+        user: UserData,
+        time: DateTime<Utc>,
+        course: CourseData,
+        assignment: AssignmentData,
+        data: ActivityData,
+    }
 
-    Ok(serde_json::to_string(&search_results)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    // the activity of all of the followed users is found through the index at once
+    let query = state
+        .db
+        .query("LET $followees = SELECT VALUE out FROM follows WHERE in == $user")
+        .query(page.activity_query(
+            "id, user.id, user.username, user.name, time, course.id, course.code, assignment.id,
+            assignment.name, data",
+            "user IN $followees",
+        ))
+        .bind(("user", record_id("user", id)));
+
+    let activity: Vec<Activity> = page
+        .bind_cursor(query)
+        .await
+        .log_map_err(|_| Status::InternalServerError)?
+        .take(1)
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    to_json(&Page::new(activity, &page, |activity| ActivityCursor {
+        time: activity.time,
+        id: activity.id.ulid(),
+    }))
+}
+
+/// Request body of [`log_user_activity`].
+#[derive(serde::Deserialize, Debug)]
+pub struct NewActivity {
+    /// ID of the course that the activity pertains to.
+    course: Ulid,
+
+    /// ID of the assignment that the activity pertains to. Must belong to `course`.
+    assignment: Ulid,
+
+    /// What the user actually did.
+    data: ActivityData,
 }
 
-/// GET "/api/uni/search/<search>": list of universities whose names match the given search
-/// string, sorted in increasing order of fuzzy distance with the search string.
+/// POST "/api/user/<id>/activity": register a new activity for the given user. The request body
+/// must be a JSON [`NewActivity`], and on success the ID of the newly created activity is
+/// returned. The caller must be the given user, or else returns 403. If a user with the given ID
+/// does not exist, returns 404. If the user does not take the
+/// given course, the assignment does not belong to the given course, or a `WorkedOn` duration is
+/// negative, returns 422.
+///
+/// Creating the activity triggers the `update_users` event, which updates the user's stats and
+/// assignment statuses accordingly.
 #[instrument(skip(state))]
-#[get("/uni/search/<search_param>", rank = 2)]
-pub async fn uni_search(
-    state: &rocket::State<State<Client>>,
-    search_param @ CleanStr(search): CleanStr<'_>,
+#[post("/user/<id_param>/activity", data = "<activity>")]
+pub async fn log_user_activity(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    authed: AuthedUser,
+    activity: Json<NewActivity>,
 ) -> Result<String, Status> {
-    #[derive(serde::Serialize, serde::Deserialize)]
-    struct SearchResult {
-        id: USId,
-        name: String,
+    authed.ensure_is(id)?;
+
+    let NewActivity {
+        course,
+        assignment,
+        data,
+    } = activity.into_inner();
+
+    let query = state
+        .db
+        .query("SELECT VALUE id FROM ONLY $user")
+        .bind(("user", record_id("user", id)));
+
+    single_query::<Option<USId>>(query)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    let query = state
+        .db
+        .query("SELECT VALUE out FROM takes_course WHERE in == $user AND out == $course")
+        .bind(("user", record_id("user", id)))
+        .bind(("course", record_id("course", course)));
+
+    let enrollments: Vec<USId> = single_query(query).await?;
+
+    if enrollments.is_empty() {
+        debug!("user does not take course {course}");
+        return Err(Status::UnprocessableEntity);
     }
 
-    let query = format!("SELECT id, name FROM university WHERE name ~ '{search}'");
+    let query = state
+        .db
+        .query("SELECT VALUE course == $course FROM ONLY $assignment")
+        .bind(("course", record_id("course", course)))
+        .bind(("assignment", record_id("assignment", assignment)));
 
-    let search_results =
-        search_table::<1, SearchResult>(&state.db, &query, search, |uni| [&uni.name]).await?;
+    if single_query::<Option<bool>>(query).await? != Some(true) {
+        debug!("assignment {assignment} does not exist or does not belong to course {course}");
+        return Err(Status::UnprocessableEntity);
+    }
 
-    Ok(serde_json::to_string(&search_results)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    if let ActivityData::WorkedOn { duration } = &data {
+        if *duration < TimeDelta::zero() {
+            return Err(Status::UnprocessableEntity);
+        }
+    }
+
+    let query = state
+        .db
+        .query(
+            "CREATE ONLY activity:ulid() SET
+                user = $user,
+                time = time::now(),
+                course = $course,
+                assignment = $assignment,
+                data = $data
+            RETURN VALUE id",
+        )
+        .bind(("user", record_id("user", id)))
+        .bind(("course", record_id("course", course)))
+        .bind(("assignment", record_id("assignment", assignment)))
+        .bind(("data", data));
+
+    let activity_id: Option<USId> = single_query(query).await?;
+
+    to_json(&activity_id.ok_or(Status::InternalServerError)?)
 }
 
-/// GET "/api/major/search/<search>": list of majors whose names match the given search
-/// string, sorted in increasing order of fuzzy distance with the search string.
+/// PATCH "/api/user/<id>/activity/<activity_id>": replace the data of an activity registered by the
+/// given user (e.g. to fix a mistyped `WorkedOn` duration). The request body must be a JSON
+/// [`ActivityData`]. The caller must be the given user, or else returns 403. If the given activity
+/// does not exist or was not registered by the given user, returns 404. If a `WorkedOn` duration is
+/// negative, returns 422.
+///
+/// The `update_users` event adjusts the user's stats and assignment statuses accordingly.
 #[instrument(skip(state))]
-#[get("/major/search/<search_param>", rank = 2)]
-pub async fn major_search(
-    state: &rocket::State<State<Client>>,
-    search_param @ CleanStr(search): CleanStr<'_>,
+#[patch("/user/<id_param>/activity/<activity_id_param>", data = "<data>")]
+pub async fn edit_user_activity(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    activity_id_param @ UlidParam(activity_id): UlidParam,
+    authed: AuthedUser,
+    data: Json<ActivityData>,
 ) -> Result<String, Status> {
-    #[derive(serde::Serialize, serde::Deserialize)]
-    struct SearchResult {
-        id: USId,
-        name: String,
+    authed.ensure_is(id)?;
+
+    let data = data.into_inner();
+
+    if let ActivityData::WorkedOn { duration } = &data {
+        if *duration < TimeDelta::zero() {
+            return Err(Status::UnprocessableEntity);
+        }
     }
 
-    let query = format!("SELECT id, name FROM major WHERE name ~ '{search}'");
+    let query = state
+        .db
+        .query(
+            "UPDATE $activity SET data = $data
+            WHERE user == $user
+            RETURN VALUE id",
+        )
+        .bind(("activity", record_id("activity", activity_id)))
+        .bind(("user", record_id("user", id)))
+        .bind(("data", data));
 
-    let search_results =
-        search_table::<1, SearchResult>(&state.db, &query, search, |major| [&major.name]).await?;
+    let activity_ids: Vec<USId> = single_query(query).await?;
 
-    Ok(serde_json::to_string(&search_results)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    to_json(activity_ids.first().ok_or(Status::NotFound)?)
 }
 
-/// GET "/api/user/<id>/activity": list of activities registered by the given user, sorted in
-/// decreasing order of recency.
+/// DELETE "/api/user/<id>/activity/<activity_id>": delete an activity registered by the given user.
+/// The caller must be the given user, or else returns 403. If the given activity does not exist or
+/// was not registered by the given user, returns 404.
+///
+/// The `update_users` event reverses the activity's effect on the user's stats and assignment
+/// statuses.
 #[instrument(skip(state))]
-#[get("/user/<id_param>/activity", rank = 3)]
-pub async fn user_activity(
-    state: &rocket::State<State<Client>>,
+#[delete("/user/<id_param>/activity/<activity_id_param>")]
+pub async fn delete_user_activity(
+    state: &rocket::State<State<Any>>,
     id_param @ UlidParam(id): UlidParam,
+    activity_id_param @ UlidParam(activity_id): UlidParam,
+    authed: AuthedUser,
 ) -> Result<String, Status> {
-    #[derive(serde::Serialize, serde::Deserialize)]
-    struct CourseData {
+    authed.ensure_is(id)?;
+
+    let query = state
+        .db
+        .query(
+            "DELETE $activity
+            WHERE user == $user
+            RETURN VALUE $before.id",
+        )
+        .bind(("activity", record_id("activity", activity_id)))
+        .bind(("user", record_id("user", id)));
+
+    let activity_ids: Vec<USId> = single_query(query).await?;
+
+    to_json(activity_ids.first().ok_or(Status::NotFound)?)
+}
+
+/// POST "/api/admin/repair_stats": recompute every user's stats and assignment statuses from
+/// scratch by replaying the activity log (see [`stats::repair`]). Returns a list of the users whose
+/// stored values differed, along with their stored and recomputed values. The caller must be an
+/// admin, or else returns 403. Activity changed during a repair may be lost, so this must only be
+/// used while no activity is being registered.
+#[instrument(skip(state))]
+#[post("/admin/repair_stats")]
+pub async fn admin_repair_stats(
+    state: &rocket::State<State<Any>>,
+    admin: AdminUser,
+) -> Result<String, Status> {
+    info!("admin {} requested a stats repair", admin.0.id);
+
+    let discrepancies = stats::repair(&state.db)
+        .await
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    to_json(&discrepancies)
+}
+
+/// Response body of [`register`] and [`login`].
+#[derive(serde::Serialize, Debug)]
+struct SessionResponse {
+    /// ID of the user who is now logged in.
+    user: Ulid,
+
+    /// Token to send in the `Authorization: Bearer <token>` header of authenticated requests.
+    token: String,
+}
+
+/// Request body of [`register`].
+#[derive(serde::Deserialize)]
+pub struct Registration {
+    username: String,
+    password: String,
+    name: Name,
+    university: Ulid,
+    major: Ulid,
+    grad_year: i32,
+}
+
+/// Minimum number of characters in a password.
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// POST "/api/register": create a new user from a JSON [`Registration`] and log them in, returning
+/// their ID and a session token. If the username is already taken, returns 409. If the username is
+/// not 1-32 ASCII alphanumeric characters or underscores, the password is shorter than
+/// [`MIN_PASSWORD_LEN`], or the given university or major does not exist, returns 422.
+#[instrument(skip_all)]
+#[post("/register", data = "<registration>")]
+pub async fn register(
+    state: &rocket::State<State<Any>>,
+    registration: Json<Registration>,
+) -> Result<String, Status> {
+    let Registration {
+        username,
+        password,
+        name,
+        university,
+        major,
+        grad_year,
+    } = registration.into_inner();
+
+    if username.is_empty()
+        || username.len() > 32
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        || password.chars().count() < MIN_PASSWORD_LEN
+    {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let query = state
+        .db
+        .query("RETURN $university.id != NONE AND $major.id != NONE")
+        .bind(("university", record_id("university", university)))
+        .bind(("major", record_id("major", major)));
+
+    if !single_query::<Option<bool>>(query).await?.unwrap_or(false) {
+        debug!("university {university} or major {major} does not exist");
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let password_hash =
+        auth::hash_password(&password).log_map_err(|_| Status::InternalServerError)?;
+
+    let query = state
+        .db
+        .query(
+            "CREATE ONLY user:ulid() SET
+                name = $name,
+                username = $username,
+                password_hash = $password_hash,
+                university = $university,
+                major = $major,
+                grad_year = $grad_year,
+                assignments_planning = [],
+                assignments_in_progress = [],
+                assignments_completed = [],
+                stats.assignments_completed = 0,
+                stats.secs_worked = 0
+            RETURN VALUE record::id(id)",
+        )
+        .bind(("name", name))
+        .bind(("username", username))
+        .bind(("password_hash", password_hash))
+        .bind(("university", record_id("university", university)))
+        .bind(("major", record_id("major", major)))
+        .bind(("grad_year", grad_year));
+
+    // checking for the username beforehand would race with concurrent registrations, so rely on
+    // the `unique_username` index instead
+    let user: Option<Ulid> = match query
+        .await
+        .log_map_err(|_| Status::InternalServerError)?
+        .take(0)
+    {
+        Err(err) if db::is_index_violation(&err, "unique_username") => {
+            debug!("username is already taken");
+            return Err(Status::Conflict);
+        }
+        result => result.log_map_err(|_| Status::InternalServerError)?,
+    };
+
+    let user = user.ok_or(Status::InternalServerError)?;
+
+    info!("registered user {user}");
+
+    let token = auth::create_session(&state.db, user)
+        .await
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    to_json(&SessionResponse { user, token })
+}
+
+/// Request body of [`login`].
+#[derive(serde::Deserialize)]
+pub struct Login {
+    username: String,
+    password: String,
+}
+
+/// POST "/api/login": log in as an existing user given a JSON [`Login`], returning their ID and a
+/// new session token. If there is no user with the given username or the password is incorrect,
+/// returns 401.
+#[instrument(skip_all)]
+#[post("/login", data = "<login>")]
+pub async fn login(
+    state: &rocket::State<State<Any>>,
+    login: Json<Login>,
+) -> Result<String, Status> {
+    #[derive(serde::Deserialize)]
+    struct Credentials {
+        id: Ulid,
+        password_hash: String,
+    }
+
+    let Login { username, password } = login.into_inner();
+
+    let query = state
+        .db
+        .query(
+            "SELECT record::id(id) AS id, password_hash FROM user
+            WHERE username == $username AND password_hash != NONE",
+        )
+        .bind(("username", username));
+
+    let credentials: Option<Credentials> = single_query(query).await?;
+
+    let Some(Credentials { id, password_hash }) = credentials else {
+        auth::verify_missing_password(&password);
+        return Err(Status::Unauthorized);
+    };
+
+    if !auth::verify_password(&password, &password_hash)
+        .log_map_err(|_| Status::InternalServerError)?
+    {
+        return Err(Status::Unauthorized);
+    }
+
+    let token = auth::create_session(&state.db, id)
+        .await
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    to_json(&SessionResponse { user: id, token })
+}
+
+/// POST "/api/logout": end the session through which the caller is authenticated.
+#[instrument(skip(state))]
+#[post("/logout")]
+pub async fn logout(state: &rocket::State<State<Any>>, authed: AuthedUser) -> Result<(), Status> {
+    state
+        .db
+        .query("DELETE $session")
+        .bind(("session", record_id("session", authed.session)))
+        .await
+        .log_map_err(|_| Status::InternalServerError)?
+        .check()
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    Ok(())
+}
+
+/// GET "/api/me": data of the calling user, in the same format as "/api/user/<id>".
+#[instrument(skip(state))]
+#[get("/me")]
+pub async fn me(state: &rocket::State<State<Any>>, authed: AuthedUser) -> Result<String, Status> {
+    let query = state
+        .db
+        .query("SELECT * FROM ONLY $user")
+        .bind(("user", record_id("user", authed.id)));
+
+    let user = single_query::<Option<User>>(query)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    to_json(&user)
+}
+
+/// Response body of [`follow`] and [`unfollow`].
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+struct FollowCounts {
+    /// Number of users that the follower is now following.
+    following: usize,
+
+    /// Number of users that now follow the target.
+    followers: usize,
+}
+
+/// Helper function for getting the [`FollowCounts`] after `user` follows or unfollows `target`.
+async fn follow_counts(
+    db: &Surreal<Any>,
+    user: Ulid,
+    target: Ulid,
+) -> Result<FollowCounts, Status> {
+    let query = db
+        .query(
+            "RETURN {
+                following: count(SELECT id FROM follows WHERE in == $user),
+                followers: count(SELECT id FROM follows WHERE out == $target)
+            }",
+        )
+        .bind(("user", record_id("user", user)))
+        .bind(("target", record_id("user", target)));
+
+    single_query::<Option<FollowCounts>>(query)
+        .await?
+        .ok_or(Status::InternalServerError)
+}
+
+/// PUT "/api/user/<id>/following/<target>": make the given user follow the target user, returning
+/// the updated [`FollowCounts`]. Following a user who is already followed does nothing. The caller
+/// must be the given user, or else returns 403. If the target user does not exist, returns 404. If
+/// the target is the given user, returns 422.
+#[instrument(skip(state))]
+#[put("/user/<id_param>/following/<target_param>")]
+pub async fn follow(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    target_param @ UlidParam(target): UlidParam,
+    authed: AuthedUser,
+) -> Result<String, Status> {
+    authed.ensure_is(id)?;
+
+    if id == target {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let query = state
+        .db
+        .query("SELECT VALUE id FROM ONLY $target")
+        .bind(("target", record_id("user", target)));
+
+    single_query::<Option<USId>>(query)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    let response = state
+        .db
+        .query("RELATE $user->follows->$target")
+        .bind(("user", record_id("user", id)))
+        .bind(("target", record_id("user", target)))
+        .await
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    // checking for an existing follow beforehand would race with concurrent follows, so rely on
+    // the `unique_follows` index instead
+    match response.check() {
+        Err(err) if db::is_index_violation(&err, "unique_follows") => {
+            debug!("user {id} already follows user {target}");
+        }
+        result => {
+            result.log_map_err(|_| Status::InternalServerError)?;
+        }
+    }
+
+    to_json(&follow_counts(&state.db, id, target).await?)
+}
+
+/// DELETE "/api/user/<id>/following/<target>": make the given user stop following the target user,
+/// returning the updated [`FollowCounts`]. Unfollowing a user who is not followed does nothing. The
+/// caller must be the given user, or else returns 403.
+#[instrument(skip(state))]
+#[delete("/user/<id_param>/following/<target_param>")]
+pub async fn unfollow(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    target_param @ UlidParam(target): UlidParam,
+    authed: AuthedUser,
+) -> Result<String, Status> {
+    authed.ensure_is(id)?;
+
+    state
+        .db
+        .query("DELETE follows WHERE in == $user AND out == $target")
+        .bind(("user", record_id("user", id)))
+        .bind(("target", record_id("user", target)))
+        .await
+        .log_map_err(|_| Status::InternalServerError)?
+        .check()
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    to_json(&follow_counts(&state.db, id, target).await?)
+}
+
+/// PUT "/api/user/<id>/courses/<course>": enroll the given user in the given course. The caller
+/// must be the given user, or else returns 403. If the course does not exist, returns 404. If the
+/// user is already enrolled in the course, returns 409. If the server only allows enrollment in
+/// courses at the user's own university and the course is at a different one, returns 422.
+#[instrument(skip(state))]
+#[put("/user/<id_param>/courses/<course_param>")]
+pub async fn enroll(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    course_param @ UlidParam(course): UlidParam,
+    authed: AuthedUser,
+) -> Result<(), Status> {
+    authed.ensure_is(id)?;
+
+    let query = state
+        .db
+        .query("SELECT VALUE university == $user.university FROM ONLY $course")
+        .bind(("user", record_id("user", id)))
+        .bind(("course", record_id("course", course)));
+
+    let same_university = single_query::<Option<bool>>(query)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    if state.restrict_enrollment_to_university && !same_university {
+        debug!("course {course} is not at the university of user {id}");
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let response = state
+        .db
+        .query("RELATE $user->takes_course->$course")
+        .bind(("user", record_id("user", id)))
+        .bind(("course", record_id("course", course)))
+        .await
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    // checking for an existing enrollment beforehand would race with concurrent enrollments, so
+    // rely on the `unique_takes_course` index instead
+    match response.check() {
+        Err(err) if db::is_index_violation(&err, "unique_takes_course") => {
+            debug!("user {id} is already enrolled in course {course}");
+            Err(Status::Conflict)
+        }
+        result => {
+            result.log_map_err(|_| Status::InternalServerError)?;
+            Ok(())
+        }
+    }
+}
+
+/// DELETE "/api/user/<id>/courses/<course>": unenroll the given user from the given course. The
+/// caller must be the given user, or else returns 403. If the user is not enrolled in the course,
+/// returns 404.
+#[instrument(skip(state))]
+#[delete("/user/<id_param>/courses/<course_param>")]
+pub async fn unenroll(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    course_param @ UlidParam(course): UlidParam,
+    authed: AuthedUser,
+) -> Result<(), Status> {
+    authed.ensure_is(id)?;
+
+    let query = state
+        .db
+        .query(
+            "DELETE takes_course WHERE in == $user AND out == $course
+            RETURN VALUE $before.out",
+        )
+        .bind(("user", record_id("user", id)))
+        .bind(("course", record_id("course", course)));
+
+    let deleted: Vec<USId> = single_query(query).await?;
+
+    if deleted.is_empty() {
+        return Err(Status::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Request body of [`create_course`].
+#[derive(serde::Deserialize, Debug)]
+pub struct NewCourse {
+    university: Ulid,
+    code: String,
+    name: String,
+
+    /// Create the course even if there are similar courses at the same university.
+    #[serde(default)]
+    force: bool,
+}
+
+/// Response body of [`create_course`] and [`create_assignment`].
+#[derive(serde::Serialize, Debug)]
+struct Created {
+    /// ID of the newly created or already existing record.
+    id: USId,
+
+    /// Whether a new record was created, as opposed to an equivalent one already existing.
+    created: bool,
+}
+
+/// POST "/api/course": create a new course at a university from a JSON [`NewCourse`]. The caller
+/// must be logged in. The course code is normalized (see [`courses::normalize_course_code`]), and
+/// if a course with the same normalized code already exists at the university, nothing is created
+/// and the existing course's ID is returned with 200. Otherwise, if there are courses at the
+/// university which are probably duplicates (see [`courses::is_similar_course`]) and `force` is
+/// not set, nothing is created and a list of those [`Course`]s is returned with 409. Otherwise,
+/// creates the course and returns its ID with 201. If the university does not exist or the code or
+/// name is blank, returns 422.
+#[instrument(skip(state))]
+#[post("/course", data = "<course>")]
+pub async fn create_course(
+    state: &rocket::State<State<Any>>,
+    authed: AuthedUser,
+    course: Json<NewCourse>,
+) -> Result<(Status, String), Status> {
+    let NewCourse {
+        university,
+        code,
+        name,
+        force,
+    } = course.into_inner();
+
+    let code = courses::normalize_course_code(&code).ok_or(Status::UnprocessableEntity)?;
+    let name = courses::normalize_name(&name);
+
+    if name.is_empty() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let query = state
+        .db
+        .query("SELECT VALUE id FROM ONLY $university")
+        .bind(("university", record_id("university", university)));
+
+    single_query::<Option<USId>>(query)
+        .await?
+        .ok_or(Status::UnprocessableEntity)?;
+
+    let query = state
+        .db
+        .query("SELECT id, university, code, name FROM course WHERE university == $university")
+        .bind(("university", record_id("university", university)));
+
+    let existing: Vec<Course> = single_query(query).await?;
+
+    // codes of courses created before normalization existed may not be normalized yet
+    if let Some(course) = existing
+        .iter()
+        .find(|course| courses::normalize_course_code(&course.code).as_ref() == Some(&code))
+    {
+        debug!("merged with existing course {}", course.id);
+
+        let created = Created {
+            id: course.id,
+            created: false,
+        };
+
+        return Ok((Status::Ok, to_json(&created)?));
+    }
+
+    let similar: Vec<&Course> = existing
+        .iter()
+        .filter(|course| courses::is_similar_course(&code, &name, &course.code, &course.name))
+        .collect();
+
+    if !similar.is_empty() && !force {
+        debug!("found {} similar courses", similar.len());
+        return Ok((Status::Conflict, to_json(&similar)?));
+    }
+
+    let query = state
+        .db
+        .query(
+            "CREATE ONLY course:ulid() SET
+                university = $university,
+                code = $code,
+                name = $name
+            RETURN VALUE id",
+        )
+        .bind(("university", record_id("university", university)))
+        .bind(("code", code))
+        .bind(("name", name));
+
+    let id: Option<USId> = single_query(query).await?;
+
+    let id = id.ok_or(Status::InternalServerError)?;
+
+    info!("user {} created course {id}", authed.id);
+
+    Ok((Status::Created, to_json(&Created { id, created: true })?))
+}
+
+/// Request body of [`create_assignment`].
+#[derive(serde::Deserialize, Debug)]
+pub struct NewAssignment {
+    name: String,
+}
+
+/// POST "/api/course/<id>/assignment": create a new assignment in the given course from a JSON
+/// [`NewAssignment`]. The caller must be enrolled in the course, or else returns 403. If an
+/// assignment with the same name (ignoring case and whitespace) already exists in the course,
+/// nothing is created and the existing assignment's ID is returned with 200. Otherwise, creates the
+/// assignment and returns its ID with 201. If the name is blank, returns 422.
+#[instrument(skip(state))]
+#[post("/course/<id_param>/assignment", data = "<assignment>")]
+pub async fn create_assignment(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    authed: AuthedUser,
+    assignment: Json<NewAssignment>,
+) -> Result<(Status, String), Status> {
+    #[derive(serde::Deserialize)]
+    struct Assignment {
         id: USId,
-        code: String,
+        name: String,
     }
 
-    #[derive(serde::Serialize, serde::Deserialize)]
-    struct Activity {
-        time: DateTime<Utc>,
-        course: CourseData,
-        assignment: String,
-        data: ActivityData,
+    let name = courses::normalize_name(&assignment.name);
+
+    if name.is_empty() {
+        return Err(Status::UnprocessableEntity);
     }
 
-    let query = format!(
-        "SELECT time, course.id, course.code, assignment, data
-        FROM activity
-        WHERE user == user:`{id}`
-        ORDER BY time DESC"
-    );
+    let query = state
+        .db
+        .query("SELECT VALUE out FROM takes_course WHERE in == $user AND out == $course")
+        .bind(("user", record_id("user", authed.id)))
+        .bind(("course", record_id("course", id)));
 
-    let activity: Vec<Activity> = single_query(&state.db, &query).await?;
+    let enrollments: Vec<USId> = single_query(query).await?;
 
-    Ok(serde_json::to_string(&activity)
-        .wrap_err("failed to serialize response")
-        .log_map_err(|_| Status::InternalServerError)?)
+    if enrollments.is_empty() {
+        debug!("user {} does not take course {id}", authed.id);
+        return Err(Status::Forbidden);
+    }
+
+    let query = state
+        .db
+        .query("SELECT id, name FROM assignment WHERE course == $course")
+        .bind(("course", record_id("course", id)));
+
+    let existing: Vec<Assignment> = single_query(query).await?;
+
+    if let Some(assignment) = existing.iter().find(|assignment| {
+        courses::normalize_name(&assignment.name).to_lowercase() == name.to_lowercase()
+    }) {
+        debug!("merged with existing assignment {}", assignment.id);
+
+        let created = Created {
+            id: assignment.id,
+            created: false,
+        };
+
+        return Ok((Status::Ok, to_json(&created)?));
+    }
+
+    let query = state
+        .db
+        .query(
+            "CREATE ONLY assignment:ulid() SET
+                course = $course,
+                name = $name
+            RETURN VALUE id",
+        )
+        .bind(("course", record_id("course", id)))
+        .bind(("name", name));
+
+    let assignment_id: Option<USId> = single_query(query).await?;
+
+    let assignment_id = assignment_id.ok_or(Status::InternalServerError)?;
+
+    Ok((
+        Status::Created,
+        to_json(&Created {
+            id: assignment_id,
+            created: true,
+        })?,
+    ))
+}
+
+/// Error response of [`set_profile_picture`].
+#[derive(Debug)]
+pub enum UploadError {
+    /// The upload was refused for exceeding a [`MediaLimit`]. Responds with 413 for the quota, or
+    /// with 429 and a `Retry-After` header for the rate limit. The body is the JSON [`MediaLimit`]
+    /// with an explanatory `message`.
+    LimitExceeded(MediaLimit),
+
+    /// Any other failure, which is forwarded to the catcher of the given status.
+    Status(Status),
+}
+
+impl From<Status> for UploadError {
+    fn from(status: Status) -> Self {
+        Self::Status(status)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for UploadError {
+    fn respond_to(self, req: &'r rocket::Request<'_>) -> response::Result<'static> {
+        #[derive(serde::Serialize)]
+        struct LimitExceeded {
+            message: String,
+
+            #[serde(flatten)]
+            limit: MediaLimit,
+        }
+
+        let limit = match self {
+            Self::LimitExceeded(limit) => limit,
+            Self::Status(status) => return Err(status),
+        };
+
+        let (status, retry_after) = match limit {
+            MediaLimit::Quota { .. } => (Status::PayloadTooLarge, None),
+            MediaLimit::Rate {
+                retry_after_secs, ..
+            } => (Status::TooManyRequests, Some(retry_after_secs)),
+        };
+
+        let message = limit.to_string();
+        let body = to_json(&LimitExceeded { message, limit })?;
+
+        let mut response = Response::build_from(body.respond_to(req)?)
+            .status(status)
+            .finalize();
+
+        if let Some(retry_after) = retry_after {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+
+        Ok(response)
+    }
+}
+
+/// PUT "/api/user/<id>/profile_picture": store the request body as the given user's new profile
+/// picture, returning its media ID. The caller must be the given user, or else returns 403. If the
+/// upload is larger than [`media::MAX_MEDIA_SIZE`], returns 413. If the upload is not a PNG, JPEG,
+/// GIF, or WebP image, returns 415. If storing the upload would put the user over their media
+/// quota or they have already uploaded too much media in the last hour, see
+/// [`UploadError::LimitExceeded`].
+///
+/// The user's previous profile picture is released, so that it stops counting against their quota.
+#[instrument(skip(state, data))]
+#[put("/user/<id_param>/profile_picture", data = "<data>")]
+pub async fn set_profile_picture(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    authed: AuthedUser,
+    data: Data<'_>,
+) -> Result<String, UploadError> {
+    authed.ensure_is(id)?;
+
+    let media_id = match media::store_media(state, id, data).await {
+        Ok(media_id) => media_id,
+        Err(MediaError::LimitExceeded(limit)) => {
+            info!("refused upload: {limit}");
+            return Err(UploadError::LimitExceeded(limit));
+        }
+        Err(MediaError::TooLarge) => {
+            info!("refused upload: {}", MediaError::TooLarge);
+            return Err(Status::PayloadTooLarge.into());
+        }
+        Err(MediaError::UnsupportedType) => {
+            info!("refused upload: {}", MediaError::UnsupportedType);
+            return Err(Status::UnsupportedMediaType.into());
+        }
+        Err(err @ MediaError::Internal(_)) => {
+            error!("{err}");
+            return Err(Status::InternalServerError.into());
+        }
+    };
+
+    // nothing else refers to profile pictures, so the old one is deleted right away rather than
+    // counting against the user's quota until it is swept. `refs` of its blob is decremented by
+    // the `count_blob_refs` event
+    state
+        .db
+        .query(
+            "BEGIN TRANSACTION;
+
+            LET $old = $user.profile_picture;
+
+            UPDATE $user SET profile_picture = $media;
+
+            IF $old != NONE AND $old != $media {
+                DELETE type::thing('media', $old);
+            };
+
+            COMMIT TRANSACTION;",
+        )
+        .bind(("user", record_id("user", id)))
+        .bind(("media", media_id))
+        .await
+        .log_map_err(|_| Status::InternalServerError)?
+        .check()
+        .log_map_err(|_| Status::InternalServerError)?;
+
+    Ok(to_json(&media_id)?)
+}
+
+/// Response of [`media_file`]: the media itself, served with the MIME type detected when it was
+/// uploaded, and with content type sniffing disabled so that browsers never second-guess it. Media
+/// never changes once it is stored, so it may be cached indefinitely.
+#[derive(Responder)]
+pub struct MediaFile {
+    body: Vec<u8>,
+    content_type: ContentType,
+    nosniff: Header<'static>,
+    cache_control: Header<'static>,
+}
+
+/// GET "/media/<id>?<size>": the media file with the given ID. If `size` is given, serves the
+/// downscaled variant of that size instead, which must be one of [`media::VARIANT_SIZES`]. If there
+/// is no such media or variant, returns 404. If `size` is not a number, returns 422.
+#[instrument(skip(state))]
+#[get("/<id_param>?<size>")]
+pub async fn media_file(
+    state: &rocket::State<State<Any>>,
+    id_param @ UlidParam(id): UlidParam,
+    size: OptionalParam<u32>,
+) -> Result<MediaFile, Status> {
+    let size = size.0;
+
+    if size.is_some_and(|size| !media::VARIANT_SIZES.contains(&size)) {
+        return Err(Status::NotFound);
+    }
+
+    #[derive(serde::Deserialize)]
+    struct MediaInfo {
+        mime: String,
+        blob: String,
+    }
+
+    let query = state
+        .db
+        .query("SELECT mime, record::id(blob) AS blob FROM ONLY $media")
+        .bind(("media", record_id("media", id)));
+
+    let MediaInfo { mime, blob } = single_query::<Option<MediaInfo>>(query)
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    let content_type = ContentType::parse_flexible(&mime).ok_or_else(|| {
+        error!("media {id} has invalid MIME type {mime:?}");
+        Status::InternalServerError
+    })?;
+
+    let body = media::read_blob(state, &blob, &mime, size)
+        .await
+        .log_map_err(|_| Status::NotFound)?;
+
+    Ok(MediaFile {
+        body,
+        content_type,
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+        cache_control: Header::new("Cache-Control", "public, max-age=31536000, immutable"),
+    })
 }
+
+#[cfg(test)]
+mod tests;
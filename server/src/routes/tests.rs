@@ -0,0 +1,2048 @@
+use crate::{
+    auth,
+    db::record_id,
+    testing::{TestServer, TestUser},
+    State,
+};
+
+use rocket::{
+    http::{ContentType, Status},
+    local::asynchronous::LocalResponse,
+};
+
+use serde_json::{json, Value};
+
+use surrealdb::engine::any::Any;
+
+use ulid::Ulid;
+
+/// A user taking a course, another user, and an assignment in that course.
+struct Fixture {
+    server: TestServer,
+    owner: TestUser,
+    other: TestUser,
+    course: Ulid,
+    assignment: Ulid,
+}
+
+impl Fixture {
+    async fn new() -> Self {
+        Self::with(|_| ()).await
+    }
+
+    /// Set up the fixture on a server whose configuration is first changed with the given function.
+    async fn with(configure: impl FnOnce(&mut State<Any>)) -> Self {
+        let server = TestServer::with(configure).await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let major = server.major("Mathematics").await;
+
+        let owner = server
+            .user("jacobhenn", ("Jacob", "Henn"), university, major)
+            .await;
+        let other = server
+            .user("choobipanda", ("Amy", "Nguyen"), university, major)
+            .await;
+
+        let course = server
+            .course(university, "MAT 4170", "Abstract Algebra I")
+            .await;
+        let assignment = server.assignment(course, "HW 2").await;
+
+        server.enroll(&owner, course).await;
+
+        Self {
+            server,
+            owner,
+            other,
+            course,
+            assignment,
+        }
+    }
+}
+
+async fn body_json(response: LocalResponse<'_>) -> Value {
+    response.into_json().await.expect("response is not JSON")
+}
+
+mod log_user_activity {
+    use super::*;
+
+    use crate::structs::ActivityData;
+
+    fn activity(fixture: &Fixture, data: Value) -> String {
+        json!({
+            "course": fixture.course.to_string(),
+            "assignment": fixture.assignment.to_string(),
+            "data": data,
+        })
+        .to_string()
+    }
+
+    async fn post<'f>(
+        fixture: &'f Fixture,
+        user: Option<&TestUser>,
+        body: String,
+    ) -> LocalResponse<'f> {
+        let mut request = fixture
+            .server
+            .client
+            .post(format!("/api/user/{}/activity", fixture.owner.id))
+            .header(ContentType::JSON)
+            .body(body);
+
+        if let Some(user) = user {
+            request = request.header(user.auth());
+        }
+
+        request.dispatch().await
+    }
+
+    #[rocket::async_test]
+    async fn stores_activity_and_updates_stats() {
+        let fixture = Fixture::new().await;
+
+        let body = activity(
+            &fixture,
+            json!({ "kind": "WorkedOn", "duration_secs": 1500 }),
+        );
+        let response = post(&fixture, Some(&fixture.owner), body).await;
+
+        assert_eq!(response.status(), Status::Ok);
+
+        let id: Ulid = serde_json::from_value(body_json(response).await).unwrap();
+
+        let stored: Option<Value> = fixture
+            .server
+            .db()
+            .query(
+                "SELECT
+                    record::id(user) AS user,
+                    record::id(course) AS course,
+                    record::id(assignment) AS assignment,
+                    data,
+                    user.stats.secs_worked AS secs_worked,
+                    user.assignments_in_progress CONTAINS assignment AS in_progress
+                FROM ONLY $activity",
+            )
+            .bind(("activity", record_id("activity", id)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        assert_eq!(
+            stored,
+            Some(json!({
+                "user": fixture.owner.id.to_string(),
+                "course": fixture.course.to_string(),
+                "assignment": fixture.assignment.to_string(),
+                "data": { "kind": "WorkedOn", "duration_secs": 1500 },
+                "secs_worked": 1500,
+                "in_progress": true,
+            }))
+        );
+    }
+
+    #[rocket::async_test]
+    async fn lists_logged_activity() {
+        let fixture = Fixture::new().await;
+
+        let body = activity(&fixture, json!({ "kind": "Completed" }));
+        let response = post(&fixture, Some(&fixture.owner), body).await;
+        assert_eq!(response.status(), Status::Ok);
+        let id: Ulid = serde_json::from_value(body_json(response).await).unwrap();
+
+        let response = fixture
+            .server
+            .client
+            .get(format!("/api/user/{}/activity", fixture.owner.id))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let page = body_json(response).await;
+        let items = page["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["id"], id.to_string());
+        assert_eq!(items[0]["course"]["code"], "MAT 4170");
+        assert_eq!(items[0]["assignment"]["id"], fixture.assignment.to_string());
+        assert_eq!(items[0]["assignment"]["name"], "HW 2");
+        assert_eq!(items[0]["data"], json!({ "kind": "Completed" }));
+    }
+
+    #[rocket::async_test]
+    async fn pages_through_activity_in_order_of_time() {
+        let fixture = Fixture::new().await;
+        let server = &fixture.server;
+
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let id = server
+                .activity(
+                    &fixture.owner,
+                    fixture.course,
+                    fixture.assignment,
+                    ActivityData::Planning,
+                )
+                .await;
+            ids.push(id);
+        }
+
+        // the newest activity is backdated before all of the others, and two of the others are
+        // registered at the same time
+        let times = [
+            "2024-09-02T10:00:00Z",
+            "2024-09-03T10:00:00Z",
+            "2024-09-03T10:00:00Z",
+            "2024-09-04T10:00:00Z",
+            "2024-09-01T10:00:00Z",
+        ];
+        for (id, time) in ids.iter().zip(times) {
+            server
+                .db()
+                .query("UPDATE $activity SET time = <datetime>$time")
+                .bind(("activity", record_id("activity", *id)))
+                .bind(("time", time))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+        }
+
+        let expected: Vec<String> = [ids[3], ids[2], ids[1], ids[0], ids[4]]
+            .iter()
+            .map(Ulid::to_string)
+            .collect();
+
+        for limit in [1, 2, 5] {
+            let mut found = Vec::new();
+            let mut url = format!("/api/user/{}/activity?limit={limit}", fixture.owner.id);
+
+            loop {
+                let response = server.client.get(&url).dispatch().await;
+                assert_eq!(response.status(), Status::Ok, "{url}");
+                let page = body_json(response).await;
+
+                let items = page["items"].as_array().expect("page has no items");
+                assert!(items.len() <= limit);
+                found.extend(
+                    items
+                        .iter()
+                        .map(|item| item["id"].as_str().unwrap().to_owned()),
+                );
+
+                let Some(cursor) = page["next_cursor"].as_str() else {
+                    break;
+                };
+
+                url = format!(
+                    "/api/user/{}/activity?limit={limit}&cursor={cursor}",
+                    fixture.owner.id
+                );
+            }
+
+            assert_eq!(found, expected, "{limit}");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_other_and_anonymous_users() {
+        let fixture = Fixture::new().await;
+
+        let body = activity(&fixture, json!({ "kind": "Planning" }));
+
+        let response = post(&fixture, Some(&fixture.other), body.clone()).await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = post(&fixture, None, body).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_missing_user() {
+        let fixture = Fixture::new().await;
+
+        let id = Ulid::new();
+        let ghost = TestUser {
+            id,
+            token: auth::create_session(fixture.server.db(), id).await.unwrap(),
+        };
+
+        let response = fixture
+            .server
+            .client
+            .post(format!("/api/user/{id}/activity"))
+            .header(ContentType::JSON)
+            .header(ghost.auth())
+            .body(activity(&fixture, json!({ "kind": "Planning" })))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_activity() {
+        let fixture = Fixture::new().await;
+
+        let other_course = fixture
+            .server
+            .course(Ulid::new(), "STA 2100", "Intro to Statistics")
+            .await;
+        let other_assignment = fixture.server.assignment(other_course, "Quiz 1").await;
+
+        let not_enrolled = json!({
+            "course": other_course.to_string(),
+            "assignment": other_assignment.to_string(),
+            "data": { "kind": "Planning" },
+        });
+
+        let wrong_course = json!({
+            "course": fixture.course.to_string(),
+            "assignment": other_assignment.to_string(),
+            "data": { "kind": "Planning" },
+        });
+
+        let negative_duration = activity(
+            &fixture,
+            json!({ "kind": "WorkedOn", "duration_secs": -60 }),
+        );
+
+        for body in [
+            not_enrolled.to_string(),
+            wrong_course.to_string(),
+            negative_duration,
+        ] {
+            let response = post(&fixture, Some(&fixture.owner), body).await;
+            assert_eq!(response.status(), Status::UnprocessableEntity);
+        }
+
+        let count: Option<usize> = fixture
+            .server
+            .db()
+            .query("RETURN count(SELECT id FROM activity)")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        assert_eq!(count, Some(0));
+    }
+
+    /// Send a request to edit (with the given data) or delete (with no data) the given activity as
+    /// the owner.
+    async fn change<'f>(
+        fixture: &'f Fixture,
+        activity: Ulid,
+        data: Option<Value>,
+    ) -> LocalResponse<'f> {
+        let url = format!("/api/user/{}/activity/{activity}", fixture.owner.id);
+        let client = &fixture.server.client;
+
+        let request = match data {
+            Some(data) => client
+                .patch(url)
+                .header(ContentType::JSON)
+                .body(data.to_string()),
+            None => client.delete(url),
+        };
+
+        request.header(fixture.owner.auth()).dispatch().await
+    }
+
+    /// The owner's stats and assignment statuses, as returned by their routes.
+    async fn user_data(fixture: &Fixture) -> (Value, Value) {
+        let client = &fixture.server.client;
+        let user = fixture.owner.id;
+
+        let response = client
+            .get(format!("/api/user/{user}/stats"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let stats = body_json(response).await;
+
+        let url = format!("/api/user/{user}/assignment_statuses");
+        let response = client.get(url).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let statuses = body_json(response).await;
+
+        (stats, statuses)
+    }
+
+    /// Assignment statuses in which the fixture's assignment is planning, in progress, and/or
+    /// completed.
+    fn statuses(fixture: &Fixture, planning: bool, in_progress: bool, completed: bool) -> Value {
+        let list = |has: bool| match has {
+            true => json!([fixture.assignment.to_string()]),
+            false => json!([]),
+        };
+
+        json!({
+            "assignments_planning": list(planning),
+            "assignments_in_progress": list(in_progress),
+            "assignments_completed": list(completed),
+        })
+    }
+
+    #[rocket::async_test]
+    async fn edits_and_deletes_update_stats_and_statuses() {
+        let fixture = Fixture::new().await;
+
+        let body = activity(
+            &fixture,
+            json!({ "kind": "WorkedOn", "duration_secs": 1500 }),
+        );
+        let response = post(&fixture, Some(&fixture.owner), body).await;
+        assert_eq!(response.status(), Status::Ok);
+        let worked_on: Ulid = serde_json::from_value(body_json(response).await).unwrap();
+
+        assert_eq!(
+            user_data(&fixture).await,
+            (
+                json!({ "assignments_completed": 0, "secs_worked": 1500 }),
+                statuses(&fixture, false, true, false),
+            )
+        );
+
+        let body = activity(&fixture, json!({ "kind": "Completed" }));
+        let response = post(&fixture, Some(&fixture.owner), body).await;
+        assert_eq!(response.status(), Status::Ok);
+        let completed: Ulid = serde_json::from_value(body_json(response).await).unwrap();
+
+        assert_eq!(
+            user_data(&fixture).await,
+            (
+                json!({ "assignments_completed": 1, "secs_worked": 1500 }),
+                statuses(&fixture, false, false, true),
+            )
+        );
+
+        let data = json!({ "kind": "WorkedOn", "duration_secs": 600 });
+        let response = change(&fixture, worked_on, Some(data)).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_json(response).await, worked_on.to_string());
+
+        assert_eq!(
+            user_data(&fixture).await,
+            (
+                json!({ "assignments_completed": 1, "secs_worked": 600 }),
+                statuses(&fixture, false, false, true),
+            )
+        );
+
+        // the assignment goes back to planning, but the earlier work still has it in progress
+        let data = json!({ "kind": "Planning" });
+        let response = change(&fixture, completed, Some(data)).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        assert_eq!(
+            user_data(&fixture).await,
+            (
+                json!({ "assignments_completed": 0, "secs_worked": 600 }),
+                statuses(&fixture, true, true, false),
+            )
+        );
+
+        let response = change(&fixture, completed, None).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_json(response).await, completed.to_string());
+
+        assert_eq!(
+            user_data(&fixture).await,
+            (
+                json!({ "assignments_completed": 0, "secs_worked": 600 }),
+                statuses(&fixture, false, true, false),
+            )
+        );
+
+        let response = change(&fixture, worked_on, None).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        assert_eq!(
+            user_data(&fixture).await,
+            (
+                json!({ "assignments_completed": 0, "secs_worked": 0 }),
+                statuses(&fixture, false, false, false),
+            )
+        );
+    }
+
+    #[rocket::async_test]
+    async fn edits_and_deletes_of_missing_activity_return_404() {
+        let fixture = Fixture::new().await;
+
+        // an activity of another user is missing as far as the owner is concerned
+        fixture.server.enroll(&fixture.other, fixture.course).await;
+        let others = fixture
+            .server
+            .activity(
+                &fixture.other,
+                fixture.course,
+                fixture.assignment,
+                ActivityData::Planning,
+            )
+            .await;
+
+        for activity in [Ulid::new(), others] {
+            let data = json!({ "kind": "Completed" });
+            let response = change(&fixture, activity, Some(data)).await;
+            assert_eq!(response.status(), Status::NotFound);
+
+            let response = change(&fixture, activity, None).await;
+            assert_eq!(response.status(), Status::NotFound);
+        }
+
+        let activity: Vec<Value> = fixture
+            .server
+            .db()
+            .query("SELECT record::id(id) AS id, data FROM activity")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        assert_eq!(
+            activity,
+            vec![json!({ "id": others.to_string(), "data": { "kind": "Planning" } })]
+        );
+    }
+}
+
+mod register {
+    use super::*;
+
+    use rocket::tokio;
+
+    /// A server with a university and a major, and a valid registration using them.
+    async fn setup() -> (TestServer, Value) {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let major = server.major("Mathematics").await;
+
+        let registration = json!({
+            "username": "jacobhenn",
+            "password": "correct horse battery staple",
+            "name": { "first": "Jacob", "last": "Henn" },
+            "university": university.to_string(),
+            "major": major.to_string(),
+            "grad_year": 2026,
+        });
+
+        (server, registration)
+    }
+
+    async fn post<'s>(server: &'s TestServer, registration: &Value) -> LocalResponse<'s> {
+        server
+            .client
+            .post("/api/register")
+            .header(ContentType::JSON)
+            .body(registration.to_string())
+            .dispatch()
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn creates_user_and_session() {
+        let (server, registration) = setup().await;
+
+        let response = post(&server, &registration).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let session = body_json(response).await;
+        let me = TestUser {
+            id: session["user"].as_str().unwrap().parse().unwrap(),
+            token: session["token"].as_str().unwrap().to_owned(),
+        };
+
+        let response = server
+            .client
+            .get("/api/me")
+            .header(me.auth())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let user = body_json(response).await;
+        assert_eq!(user["id"], me.id.to_string());
+        assert_eq!(user["username"], "jacobhenn");
+    }
+
+    #[rocket::async_test]
+    async fn rejects_taken_username() {
+        let (server, registration) = setup().await;
+
+        assert_eq!(post(&server, &registration).await.status(), Status::Ok);
+        assert_eq!(
+            post(&server, &registration).await.status(),
+            Status::Conflict
+        );
+    }
+
+    #[rocket::async_test]
+    async fn rejects_concurrent_duplicates() {
+        let (server, registration) = setup().await;
+
+        let (first, second) =
+            tokio::join!(post(&server, &registration), post(&server, &registration),);
+
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort_by_key(|status| status.code);
+        assert_eq!(statuses, [Status::Ok, Status::Conflict]);
+
+        let count: Option<usize> = server
+            .db()
+            .query("RETURN count(SELECT id FROM user WHERE username == 'jacobhenn')")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        assert_eq!(count, Some(1));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_registrations() {
+        let (server, registration) = setup().await;
+
+        let invalid = [
+            ("username", json!("")),
+            ("username", json!("a".repeat(33))),
+            ("username", json!("jacob henn")),
+            ("password", json!("hunter2")),
+            ("university", json!(Ulid::new().to_string())),
+            ("major", json!(Ulid::new().to_string())),
+        ];
+
+        for (field, value) in invalid {
+            let mut registration = registration.clone();
+            registration[field] = value;
+
+            let response = post(&server, &registration).await;
+            assert_eq!(response.status(), Status::UnprocessableEntity, "{field}");
+        }
+    }
+}
+
+mod login {
+    use super::*;
+
+    /// A server with a single user "jacobhenn" whose password is "correct horse battery staple".
+    async fn setup() -> (TestServer, Ulid) {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let major = server.major("Mathematics").await;
+        let user = server
+            .user("jacobhenn", ("Jacob", "Henn"), university, major)
+            .await;
+
+        server
+            .db()
+            .query("UPDATE $user SET password_hash = $password_hash")
+            .bind(("user", record_id("user", user.id)))
+            .bind((
+                "password_hash",
+                auth::hash_password("correct horse battery staple").unwrap(),
+            ))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        (server, user.id)
+    }
+
+    async fn post<'s>(server: &'s TestServer, username: &str, password: &str) -> LocalResponse<'s> {
+        server
+            .client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(json!({ "username": username, "password": password }).to_string())
+            .dispatch()
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn creates_session() {
+        let (server, id) = setup().await;
+
+        let response = post(&server, "jacobhenn", "correct horse battery staple").await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let session = body_json(response).await;
+        assert_eq!(session["user"], id.to_string());
+
+        let me = TestUser {
+            id,
+            token: session["token"].as_str().unwrap().to_owned(),
+        };
+
+        let response = server
+            .client
+            .get("/api/me")
+            .header(me.auth())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_wrong_credentials() {
+        let (server, _) = setup().await;
+
+        let response = post(&server, "jacobhenn", "incorrect horse battery staple").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = post(&server, "choobipanda", "correct horse battery staple").await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
+
+mod authorization {
+    use super::*;
+
+    use crate::{structs::ActivityData, testing};
+
+    use rocket::http::Method;
+
+    /// A request to a route which requires authorization.
+    struct Request {
+        method: Method,
+        uri: String,
+        body: Option<(ContentType, Vec<u8>)>,
+    }
+
+    impl Request {
+        fn new(method: Method, uri: String) -> Self {
+            Self {
+                method,
+                uri,
+                body: None,
+            }
+        }
+
+        fn json(method: Method, uri: String, body: Value) -> Self {
+            Self {
+                method,
+                uri,
+                body: Some((ContentType::JSON, body.to_string().into_bytes())),
+            }
+        }
+
+        async fn send(&self, server: &TestServer, user: Option<&TestUser>) -> Status {
+            let mut request = server.client.req(self.method, self.uri.clone());
+
+            if let Some((content_type, body)) = &self.body {
+                request = request.header(content_type.clone()).body(body);
+            }
+
+            if let Some(user) = user {
+                request = request.header(user.auth());
+            }
+
+            request.dispatch().await.status()
+        }
+    }
+
+    /// Send the request anonymously, then as `denied`, then as `allowed`, checking that it is
+    /// refused with 401 and 403 and then succeeds.
+    async fn check(server: &TestServer, request: Request, denied: &TestUser, allowed: &TestUser) {
+        let uri = format!("{} {}", request.method, request.uri);
+
+        let status = request.send(server, None).await;
+        assert_eq!(status, Status::Unauthorized, "{uri}");
+
+        let status = request.send(server, Some(denied)).await;
+        assert_eq!(status, Status::Forbidden, "{uri}");
+
+        let status = request.send(server, Some(allowed)).await;
+        assert_eq!(
+            status.class(),
+            rocket::http::StatusClass::Success,
+            "{uri}: {status}"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn only_owner_may_mutate_user_data() {
+        let Fixture {
+            server,
+            owner,
+            other,
+            course,
+            assignment,
+        } = Fixture::new().await;
+
+        let other_course = server
+            .course(Ulid::new(), "STA 2100", "Intro to Statistics")
+            .await;
+        let activity = server
+            .activity(&owner, course, assignment, ActivityData::Planning)
+            .await;
+
+        let user = format!("/api/user/{}", owner.id);
+
+        let requests = [
+            Request::json(
+                Method::Post,
+                format!("{user}/activity"),
+                json!({
+                    "course": course.to_string(),
+                    "assignment": assignment.to_string(),
+                    "data": { "kind": "Planning" },
+                }),
+            ),
+            Request::json(
+                Method::Patch,
+                format!("{user}/activity/{activity}"),
+                json!({ "kind": "Completed" }),
+            ),
+            Request::new(Method::Put, format!("{user}/following/{}", other.id)),
+            Request::new(Method::Delete, format!("{user}/following/{}", other.id)),
+            Request::new(Method::Put, format!("{user}/courses/{other_course}")),
+            Request::new(Method::Delete, format!("{user}/courses/{other_course}")),
+            Request {
+                method: Method::Put,
+                uri: format!("{user}/profile_picture"),
+                body: Some((ContentType::PNG, testing::png(8, 8))),
+            },
+            Request::new(Method::Delete, format!("{user}/activity/{activity}")),
+        ];
+
+        for request in requests {
+            check(&server, request, &other, &owner).await;
+        }
+    }
+
+    #[rocket::async_test]
+    async fn only_enrolled_users_may_create_assignments() {
+        let Fixture {
+            server,
+            owner,
+            other,
+            course,
+            ..
+        } = Fixture::new().await;
+
+        let request = Request::json(
+            Method::Post,
+            format!("/api/course/{course}/assignment"),
+            json!({ "name": "HW 3" }),
+        );
+
+        check(&server, request, &other, &owner).await;
+    }
+
+    #[rocket::async_test]
+    async fn only_admins_may_use_admin_routes() {
+        let Fixture {
+            server,
+            owner,
+            other,
+            ..
+        } = Fixture::new().await;
+
+        auth::grant_admin(server.db(), "jacobhenn".to_owned())
+            .await
+            .unwrap();
+
+        let request = Request::new(Method::Post, "/api/admin/repair_stats".to_owned());
+
+        check(&server, request, &other, &owner).await;
+    }
+
+    #[rocket::async_test]
+    async fn logged_in_users_may_create_courses() {
+        let Fixture { server, owner, .. } = Fixture::new().await;
+
+        let university = server.university("Cal State LA").await;
+
+        let request = Request::json(
+            Method::Post,
+            "/api/course".to_owned(),
+            json!({
+                "university": university.to_string(),
+                "code": "MATH 4550",
+                "name": "Complex Variables",
+            }),
+        );
+
+        assert_eq!(request.send(&server, None).await, Status::Unauthorized);
+        assert_eq!(request.send(&server, Some(&owner)).await, Status::Created);
+    }
+
+    #[rocket::async_test]
+    async fn granting_admin_requires_existing_user() {
+        let server = TestServer::new().await;
+
+        assert!(auth::grant_admin(server.db(), "nobody".to_owned())
+            .await
+            .is_err());
+    }
+}
+
+mod follow {
+    use super::*;
+
+    use rocket::tokio;
+
+    async fn put<'f>(fixture: &'f Fixture, target: Ulid) -> LocalResponse<'f> {
+        fixture
+            .server
+            .client
+            .put(format!("/api/user/{}/following/{target}", fixture.owner.id))
+            .header(fixture.owner.auth())
+            .dispatch()
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn returns_follow_counts() {
+        let fixture = Fixture::new().await;
+        let third = fixture
+            .server
+            .user("alexkim", ("Alex", "Kim"), Ulid::new(), Ulid::new())
+            .await;
+        fixture.server.follow(&third, &fixture.other).await;
+
+        let response = put(&fixture, fixture.other.id).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            body_json(response).await,
+            json!({ "following": 1, "followers": 2 })
+        );
+
+        let response = put(&fixture, third.id).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            body_json(response).await,
+            json!({ "following": 2, "followers": 1 })
+        );
+    }
+
+    #[rocket::async_test]
+    async fn repeated_follow_does_nothing() {
+        let fixture = Fixture::new().await;
+
+        let response = put(&fixture, fixture.other.id).await;
+        assert_eq!(response.status(), Status::Ok);
+        let counts = body_json(response).await;
+        assert_eq!(counts, json!({ "following": 1, "followers": 1 }));
+
+        let response = put(&fixture, fixture.other.id).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(body_json(response).await, counts);
+    }
+
+    #[rocket::async_test]
+    async fn concurrent_follows_both_succeed() {
+        let fixture = Fixture::new().await;
+
+        let (first, second) = tokio::join!(
+            put(&fixture, fixture.other.id),
+            put(&fixture, fixture.other.id),
+        );
+        assert_eq!(first.status(), Status::Ok);
+        assert_eq!(second.status(), Status::Ok);
+
+        let count: Option<usize> = fixture
+            .server
+            .db()
+            .query("RETURN count(SELECT id FROM follows)")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        assert_eq!(count, Some(1));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_self_follow_and_missing_target() {
+        let fixture = Fixture::new().await;
+
+        let response = put(&fixture, fixture.owner.id).await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let response = put(&fixture, Ulid::new()).await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
+
+mod enroll {
+    use super::*;
+
+    use rocket::tokio;
+
+    async fn put(fixture: &Fixture, course: Ulid) -> LocalResponse<'_> {
+        fixture
+            .server
+            .client
+            .put(format!("/api/user/{}/courses/{course}", fixture.owner.id))
+            .header(fixture.owner.auth())
+            .dispatch()
+            .await
+    }
+
+    async fn delete(fixture: &Fixture, course: Ulid) -> LocalResponse<'_> {
+        fixture
+            .server
+            .client
+            .delete(format!("/api/user/{}/courses/{course}", fixture.owner.id))
+            .header(fixture.owner.auth())
+            .dispatch()
+            .await
+    }
+
+    /// The number of courses that the owner is enrolled in.
+    async fn enrollments(fixture: &Fixture) -> Option<usize> {
+        fixture
+            .server
+            .db()
+            .query("RETURN count(SELECT id FROM takes_course WHERE in == $user)")
+            .bind(("user", record_id("user", fixture.owner.id)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn enrolls_and_unenrolls() {
+        let fixture = Fixture::new().await;
+
+        let response = delete(&fixture, fixture.course).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(enrollments(&fixture).await, Some(0));
+
+        let response = put(&fixture, fixture.course).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(enrollments(&fixture).await, Some(1));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_duplicate_enrollment() {
+        let fixture = Fixture::new().await;
+
+        let response = put(&fixture, fixture.course).await;
+        assert_eq!(response.status(), Status::Conflict);
+        assert_eq!(enrollments(&fixture).await, Some(1));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_concurrent_duplicates() {
+        let fixture = Fixture::new().await;
+
+        let course = fixture
+            .server
+            .course(Ulid::new(), "STA 2100", "Intro to Statistics")
+            .await;
+
+        let (first, second) = tokio::join!(put(&fixture, course), put(&fixture, course));
+
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort_by_key(|status| status.code);
+        assert_eq!(statuses, [Status::Ok, Status::Conflict]);
+        assert_eq!(enrollments(&fixture).await, Some(2));
+    }
+
+    #[rocket::async_test]
+    async fn restricts_enrollment_to_university() {
+        let fixture = Fixture::with(|state| state.restrict_enrollment_to_university = true).await;
+
+        let other_university = fixture.server.university("Cal Poly Humboldt").await;
+        let other_course = fixture
+            .server
+            .course(other_university, "STA 2100", "Intro to Statistics")
+            .await;
+
+        let response = put(&fixture, other_course).await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert_eq!(enrollments(&fixture).await, Some(1));
+
+        let university: Option<Ulid> = fixture
+            .server
+            .db()
+            .query("SELECT VALUE record::id(university) FROM ONLY $course")
+            .bind(("course", record_id("course", fixture.course)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+        let same_course = fixture
+            .server
+            .course(university.unwrap(), "STA 2100", "Intro to Statistics")
+            .await;
+
+        let response = put(&fixture, same_course).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(enrollments(&fixture).await, Some(2));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_missing_course_and_enrollment() {
+        let fixture = Fixture::new().await;
+
+        let response = put(&fixture, Ulid::new()).await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let course = fixture
+            .server
+            .course(Ulid::new(), "STA 2100", "Intro to Statistics")
+            .await;
+        let response = delete(&fixture, course).await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(enrollments(&fixture).await, Some(1));
+    }
+}
+
+mod set_profile_picture {
+    use super::*;
+
+    use crate::testing;
+
+    use rocket::{data::ByteUnit, tokio};
+
+    async fn put<'f>(
+        fixture: &'f Fixture,
+        content_type: ContentType,
+        body: Vec<u8>,
+    ) -> LocalResponse<'f> {
+        fixture
+            .server
+            .client
+            .put(format!("/api/user/{}/profile_picture", fixture.owner.id))
+            .header(content_type)
+            .header(fixture.owner.auth())
+            .body(body)
+            .dispatch()
+            .await
+    }
+
+    #[rocket::async_test]
+    async fn serves_picture_with_sniffed_type() {
+        let fixture = Fixture::new().await;
+
+        // the declared content type is ignored in favor of the actual data
+        let response = put(&fixture, ContentType::JPEG, testing::png(8, 8)).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let media: Ulid = serde_json::from_value(body_json(response).await).unwrap();
+
+        let response = fixture
+            .server
+            .client
+            .get(format!("/media/{media}"))
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(
+            response.headers().get_one("X-Content-Type-Options"),
+            Some("nosniff")
+        );
+    }
+
+    #[rocket::async_test]
+    async fn serves_downscaled_variants() {
+        let fixture = Fixture::new().await;
+
+        let response = put(&fixture, ContentType::PNG, testing::png(300, 300)).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let media: Ulid = serde_json::from_value(body_json(response).await).unwrap();
+
+        let get = |query: &str| {
+            fixture
+                .server
+                .client
+                .get(format!("/media/{media}{query}"))
+                .dispatch()
+        };
+
+        let response = get("?size=64").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("public, max-age=31536000, immutable")
+        );
+
+        let variant = image::load_from_memory(&response.into_bytes().await.unwrap()).unwrap();
+        assert_eq!((variant.width(), variant.height()), (64, 64));
+
+        assert_eq!(get("?size=100").await.status(), Status::NotFound);
+        assert_eq!(get("?size=big").await.status(), Status::UnprocessableEntity);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_spoofed_content_type() {
+        let fixture = Fixture::new().await;
+
+        let html = b"<!DOCTYPE html><script>alert(document.cookie)</script>".to_vec();
+
+        let response = put(&fixture, ContentType::PNG, html).await;
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+
+        let pictures: Option<usize> = fixture
+            .server
+            .db()
+            .query("RETURN count(SELECT id FROM media)")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        assert_eq!(pictures, Some(0));
+    }
+
+    /// The IDs of the owner's media.
+    async fn media(fixture: &Fixture) -> Vec<Ulid> {
+        fixture
+            .server
+            .db()
+            .query("SELECT VALUE record::id(id) FROM media WHERE uploader == $user")
+            .bind(("user", record_id("user", fixture.owner.id)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn releases_old_picture() {
+        let old = testing::png(16, 16);
+        let new = testing::png(17, 17);
+
+        // enough for both pictures, but not for a third
+        let quota = (old.len() + new.len()) as u64;
+        let fixture = Fixture::with(|state| state.media_quota = ByteUnit::Byte(quota)).await;
+
+        for picture in [old.clone(), new, old] {
+            let response = put(&fixture, ContentType::PNG, picture).await;
+            assert_eq!(response.status(), Status::Ok);
+
+            let media: Ulid = serde_json::from_value(body_json(response).await).unwrap();
+            assert_eq!(self::media(&fixture).await, [media]);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn refuses_upload_over_quota() {
+        let fixture = Fixture::with(|state| state.media_quota = ByteUnit::Byte(64)).await;
+
+        let response = put(&fixture, ContentType::PNG, testing::png(64, 64)).await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert_eq!(response.headers().get_one("Retry-After"), None);
+
+        let body = body_json(response).await;
+        assert_eq!(body["limit"], "quota");
+        assert_eq!(body["quota_bytes"], 64);
+
+        assert!(media(&fixture).await.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn refuses_upload_over_rate_limit() {
+        let fixture = Fixture::with(|state| state.media_uploads_per_hour = 1).await;
+
+        let response = put(&fixture, ContentType::PNG, testing::png(8, 8)).await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = put(&fixture, ContentType::PNG, testing::png(9, 9)).await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+
+        let retry_after: i64 = response
+            .headers()
+            .get_one("Retry-After")
+            .expect("no Retry-After header")
+            .parse()
+            .unwrap();
+        assert!((3590..=3600).contains(&retry_after), "{retry_after}");
+
+        let body = body_json(response).await;
+        assert_eq!(body["limit"], "rate");
+        assert_eq!(body["retry_after_secs"], retry_after);
+
+        assert_eq!(media(&fixture).await.len(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn refuses_concurrent_uploads_over_rate_limit() {
+        let fixture = Fixture::with(|state| state.media_uploads_per_hour = 1).await;
+
+        let (first, second) = tokio::join!(
+            put(&fixture, ContentType::PNG, testing::png(8, 8)),
+            put(&fixture, ContentType::PNG, testing::png(9, 9)),
+        );
+
+        let mut statuses = [first.status(), second.status()];
+        statuses.sort_by_key(|status| status.code);
+        assert_eq!(statuses, [Status::Ok, Status::TooManyRequests]);
+
+        assert_eq!(media(&fixture).await.len(), 1);
+    }
+}
+
+mod search {
+    use super::*;
+
+    async fn get(server: &TestServer, url: &str) -> Value {
+        let response = server.client.get(url).dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "{url}");
+
+        body_json(response).await
+    }
+
+    /// The IDs of the items of a page.
+    fn ids(page: &Value) -> Vec<String> {
+        page["items"]
+            .as_array()
+            .expect("page has no items")
+            .iter()
+            .map(|item| item["id"].as_str().expect("item has no ID").to_owned())
+            .collect()
+    }
+
+    /// Follow `next_cursor` from the first page of the given search route to the last, returning
+    /// the IDs of all of the items.
+    async fn all_pages(server: &TestServer, url: &str, limit: usize) -> Vec<String> {
+        let mut items = Vec::new();
+        let mut page = get(server, &format!("{url}&limit={limit}")).await;
+
+        loop {
+            let page_ids = ids(&page);
+            assert!(page_ids.len() <= limit);
+            items.extend(page_ids);
+
+            let Some(cursor) = page["next_cursor"].as_str() else {
+                break;
+            };
+
+            page = get(server, &format!("{url}&limit={limit}&cursor={cursor}")).await;
+        }
+
+        items
+    }
+
+    #[rocket::async_test]
+    async fn pages_through_all_results_in_order() {
+        let server = TestServer::new().await;
+
+        for name in [
+            "Biology",
+            "Marine Biology",
+            "Molecular Biology",
+            "Cell and Molecular Biology",
+            "Biology Education",
+            "Plant Biology",
+            "Evolutionary Biology",
+        ] {
+            server.major(name).await;
+        }
+
+        for url in [
+            "/api/major/search/biology?",
+            "/api/search?q=biology&types=major",
+        ] {
+            let expected = ids(&get(&server, &format!("{url}&limit=200")).await);
+            assert_eq!(expected.len(), 7);
+
+            for limit in [1, 2, 3, 7] {
+                assert_eq!(
+                    all_pages(&server, url, limit).await,
+                    expected,
+                    "{url} {limit}"
+                );
+            }
+        }
+    }
+
+    #[rocket::async_test]
+    async fn pages_on_to_results_with_typos() {
+        let server = TestServer::new().await;
+
+        let mut expected = Vec::new();
+        for name in ["Lancaster University", "Lancaster Bible College"] {
+            expected.push(server.university(name).await.to_string());
+        }
+        expected.sort();
+
+        let typo = server.university("Lancsater Polytechnic").await;
+        expected.push(typo.to_string());
+
+        server.university("Cal Poly Pomona").await;
+
+        for url in [
+            "/api/uni/search/lancaster?",
+            "/api/search?q=lancaster&types=uni",
+        ] {
+            // the first pages are filled without looking for typos, and later ones look for them
+            for limit in [1, 2, 3] {
+                let mut found = all_pages(&server, url, limit).await;
+                found[..2].sort();
+                assert_eq!(found, expected, "{url} {limit}");
+            }
+        }
+    }
+
+    #[rocket::async_test]
+    async fn resumes_after_deleted_cursor_result() {
+        let server = TestServer::new().await;
+
+        for _ in 0..6 {
+            server.major("Biology").await;
+        }
+
+        let first = get(&server, "/api/major/search/biology?limit=3").await;
+        let cursor = first["next_cursor"].as_str().expect("no next cursor");
+        let second_url = format!("/api/major/search/biology?limit=3&cursor={cursor}");
+        let second = ids(&get(&server, &second_url).await);
+        assert_eq!(second.len(), 3);
+
+        let deleted: Ulid = ids(&first)[2].parse().unwrap();
+        server
+            .db()
+            .query("DELETE $major")
+            .bind(("major", record_id("major", deleted)))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        assert_eq!(ids(&get(&server, &second_url).await), second);
+    }
+
+    #[rocket::async_test]
+    async fn finds_names_with_apostrophes_hyphens_and_accents() {
+        let server = TestServer::new().await;
+
+        let university = server.university("Universität Wien").await;
+        server.university("Cal Poly Pomona").await;
+        let major = server.major("Physics").await;
+
+        let mut users = Vec::new();
+        for (username, name) in [
+            ("cobrien", ("Conor", "O'Brien")),
+            ("mkolsen", ("Mary-Kate", "Olsen")),
+            ("anders", ("Anders", "Ångström")),
+            ("alexkim", ("Alex", "Kim")),
+            ("samrivera", ("Sam", "Rivera")),
+        ] {
+            let user = server.user(username, name, university, major).await;
+            users.push(user.id.to_string());
+        }
+
+        for (search, expected) in [
+            ("O%27Brien", &users[0]),
+            ("Mary-Kate", &users[1]),
+            ("Mary-Kate%20Olsen", &users[1]),
+            ("%C3%85ngstr%C3%B6m", &users[2]),
+        ] {
+            let page = get(&server, &format!("/api/user/search/{search}")).await;
+            assert_eq!(ids(&page).first(), Some(expected), "{search}");
+
+            let page = get(&server, &format!("/api/search?q={search}&types=user")).await;
+            assert_eq!(ids(&page).first(), Some(expected), "{search}");
+        }
+
+        let page = get(&server, "/api/uni/search/Universit%C3%A4t%20Wien").await;
+        assert_eq!(ids(&page), [university.to_string()]);
+
+        // search strings are bound as parameters rather than interpolated into queries
+        let page = get(&server, "/api/user/search/%27%3B%20DELETE%20user%3B%20--").await;
+        assert!(ids(&page).is_empty());
+
+        let count: Option<usize> = server
+            .db()
+            .query("RETURN count(SELECT id FROM user)")
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+        assert_eq!(count, Some(users.len()));
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_cursor() {
+        let server = TestServer::new().await;
+
+        for cursor in ["nonsense", &Ulid::new().to_string(), "0.5_nonsense"] {
+            let url = format!("/api/major/search/biology?cursor={cursor}");
+            let response = server.client.get(&url).dispatch().await;
+            assert_eq!(response.status(), Status::UnprocessableEntity, "{cursor}");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn unified_search_applies_filters_to_kinds_they_concern() {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let other_university = server.university("Cal Poly Humboldt").await;
+        let major = server.major("Algebra").await;
+
+        let user = server
+            .user("alexkim", ("Alex", "Kim"), university, major)
+            .await;
+        server
+            .user("alexlee", ("Alex", "Lee"), other_university, major)
+            .await;
+
+        let course = server.course(university, "MAT 3100", "Algebra").await;
+        server.course(other_university, "MATH 310", "Algebra").await;
+
+        let mut found = ids(&get(
+            &server,
+            &format!("/api/search?q=algebra&university={university}"),
+        )
+        .await);
+        found.sort();
+        let mut expected = vec![course.to_string(), major.to_string()];
+        expected.sort();
+        assert_eq!(found, expected);
+
+        let found = ids(&get(
+            &server,
+            &format!("/api/search?q=alex&university={university}"),
+        )
+        .await);
+        assert_eq!(found, [user.id.to_string()]);
+
+        let response = server
+            .client
+            .get("/api/search?q=alex&university=nonsense")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+    }
+
+    /// A searcher at a university taking a course, and two users with the same name at another
+    /// university. Neither is related to the searcher yet; the second one has the greater ID, so it
+    /// ranks last if the two tie.
+    struct BoostFixture {
+        server: TestServer,
+        searcher: TestUser,
+        university: Ulid,
+        other_university: Ulid,
+        major: Ulid,
+        course: Ulid,
+        plain: TestUser,
+        boosted: TestUser,
+    }
+
+    impl BoostFixture {
+        async fn new() -> Self {
+            let server = TestServer::new().await;
+
+            let university = server.university("Cal Poly Pomona").await;
+            let other_university = server.university("Cal Poly Humboldt").await;
+            let major = server.major("Mathematics").await;
+            let course = server
+                .course(university, "MAT 4170", "Abstract Algebra I")
+                .await;
+
+            let searcher = server
+                .user("jacobhenn", ("Jacob", "Henn"), university, major)
+                .await;
+            server.enroll(&searcher, course).await;
+
+            let plain = server
+                .user("samrivera1", ("Sam", "Rivera"), other_university, major)
+                .await;
+            let boosted = server
+                .user("samrivera2", ("Sam", "Rivera"), other_university, major)
+                .await;
+
+            Self {
+                server,
+                searcher,
+                university,
+                other_university,
+                major,
+                course,
+                plain,
+                boosted,
+            }
+        }
+
+        /// Search for both users, as the searcher if `logged_in`, returning the IDs and relevances
+        /// of the results.
+        async fn search(&self, logged_in: bool) -> Vec<(String, f64)> {
+            let mut request = self
+                .server
+                .client
+                .get("/api/search?q=sam%20rivera&types=user");
+
+            if logged_in {
+                request = request.header(self.searcher.auth());
+            }
+
+            let response = request.dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+
+            body_json(response).await["items"]
+                .as_array()
+                .expect("page has no items")
+                .iter()
+                .map(|item| {
+                    (
+                        item["id"].as_str().unwrap().to_owned(),
+                        item["relevance"].as_f64().unwrap(),
+                    )
+                })
+                .collect()
+        }
+
+        /// Check that the boosted user outranks the plain one for the searcher, but that they tie
+        /// for an anonymous search.
+        async fn check_boosted(&self) {
+            let plain = self.plain.id.to_string();
+            let boosted = self.boosted.id.to_string();
+
+            let results = self.search(true).await;
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].0, boosted);
+            assert_eq!(results[1].0, plain);
+            assert!(results[0].1 > results[1].1);
+
+            let results = self.search(false).await;
+            assert_eq!(results.len(), 2);
+            assert_eq!(results[0].0, plain);
+            assert_eq!(results[1].0, boosted);
+            assert_eq!(results[0].1, results[1].1);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn unrelated_users_tie() {
+        let fixture = BoostFixture::new().await;
+
+        let results = fixture.search(true).await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, fixture.plain.id.to_string());
+        assert_eq!(results[0].1, results[1].1);
+    }
+
+    #[rocket::async_test]
+    async fn boosts_users_at_same_university() {
+        let fixture = BoostFixture::new().await;
+
+        fixture
+            .server
+            .db()
+            .query("UPDATE $user SET university = $university")
+            .bind(("user", record_id("user", fixture.boosted.id)))
+            .bind(("university", record_id("university", fixture.university)))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        fixture.check_boosted().await;
+    }
+
+    #[rocket::async_test]
+    async fn boosts_users_in_shared_course() {
+        let fixture = BoostFixture::new().await;
+
+        fixture
+            .server
+            .enroll(&fixture.boosted, fixture.course)
+            .await;
+
+        fixture.check_boosted().await;
+    }
+
+    #[rocket::async_test]
+    async fn boosts_followed_users() {
+        let fixture = BoostFixture::new().await;
+
+        fixture
+            .server
+            .follow(&fixture.searcher, &fixture.boosted)
+            .await;
+
+        fixture.check_boosted().await;
+    }
+
+    #[rocket::async_test]
+    async fn boosts_users_followed_by_followed_users() {
+        let fixture = BoostFixture::new().await;
+
+        let friend = fixture
+            .server
+            .user(
+                "choobipanda",
+                ("Amy", "Nguyen"),
+                fixture.other_university,
+                fixture.major,
+            )
+            .await;
+        fixture.server.follow(&fixture.searcher, &friend).await;
+        fixture.server.follow(&friend, &fixture.boosted).await;
+
+        fixture.check_boosted().await;
+    }
+
+    #[rocket::async_test]
+    async fn each_user_filter_narrows_results() {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let other_university = server.university("Cal Poly Humboldt").await;
+        let major = server.major("Mathematics").await;
+        let other_major = server.major("Physics").await;
+        let course = server
+            .course(university, "MAT 4170", "Abstract Algebra I")
+            .await;
+
+        // the first user matches every filter, and each of the others fails exactly one of them
+        let matching = server
+            .user("alexkim", ("Alex", "Kim"), university, major)
+            .await;
+        server.enroll(&matching, course).await;
+
+        let elsewhere = server
+            .user("alexlee", ("Alex", "Lee"), other_university, major)
+            .await;
+        server.enroll(&elsewhere, course).await;
+
+        let physicist = server
+            .user("alexpark", ("Alex", "Park"), university, other_major)
+            .await;
+        server.enroll(&physicist, course).await;
+
+        let senior = server
+            .user("alexchoi", ("Alex", "Choi"), university, major)
+            .await;
+        server.enroll(&senior, course).await;
+        server
+            .db()
+            .query("UPDATE $user SET grad_year = 2025")
+            .bind(("user", record_id("user", senior.id)))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let unenrolled = server
+            .user("alexwong", ("Alex", "Wong"), university, major)
+            .await;
+
+        let search = |filters: String| {
+            let server = &server;
+
+            async move {
+                let mut found =
+                    ids(&get(server, &format!("/api/user/search/alex?{filters}")).await);
+                found.sort();
+                found
+            }
+        };
+
+        let expect = |users: &[&TestUser]| {
+            let mut ids: Vec<String> = users.iter().map(|user| user.id.to_string()).collect();
+            ids.sort();
+            ids
+        };
+
+        let everyone = [&matching, &elsewhere, &physicist, &senior, &unenrolled];
+        assert_eq!(search(String::new()).await, expect(&everyone));
+
+        for (filter, excluded) in [
+            (format!("university={university}"), &elsewhere),
+            (format!("major={major}"), &physicist),
+            ("grad_year=2026".to_owned(), &senior),
+            (format!("course={course}"), &unenrolled),
+        ] {
+            let users: Vec<&TestUser> = everyone
+                .into_iter()
+                .filter(|user| user.id != excluded.id)
+                .collect();
+            assert_eq!(search(filter.clone()).await, expect(&users), "{filter}");
+        }
+
+        let all_filters =
+            format!("university={university}&major={major}&grad_year=2026&course={course}");
+        assert_eq!(search(all_filters).await, expect(&[&matching]));
+    }
+
+    #[rocket::async_test]
+    async fn course_filter_narrows_results() {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let other_university = server.university("Cal Poly Humboldt").await;
+
+        let course = server.course(university, "MAT 3100", "Algebra").await;
+        server.course(other_university, "MATH 310", "Algebra").await;
+
+        let found = get(&server, "/api/course/search/algebra?").await;
+        assert_eq!(ids(&found).len(), 2);
+
+        let found = get(
+            &server,
+            &format!("/api/course/search/algebra?university={university}"),
+        )
+        .await;
+        assert_eq!(ids(&found), [course.to_string()]);
+    }
+
+    #[rocket::async_test]
+    async fn rejects_invalid_filters() {
+        let server = TestServer::new().await;
+
+        for url in [
+            "/api/user/search/alex?university=nonsense",
+            "/api/user/search/alex?major=nonsense",
+            "/api/user/search/alex?grad_year=nonsense",
+            "/api/user/search/alex?course=nonsense",
+            "/api/course/search/algebra?university=nonsense",
+        ] {
+            let response = server.client.get(url).dispatch().await;
+            assert_eq!(response.status(), Status::UnprocessableEntity, "{url}");
+        }
+    }
+}
+
+mod user_feed {
+    use super::*;
+
+    use crate::structs::ActivityData;
+
+    /// The owner follows the other user and two more, who have all logged activity along with a
+    /// user that the owner doesn't follow. Returns the IDs of the activity of the followed users,
+    /// newest first. Later activity is backdated before earlier activity, and pairs of activities
+    /// are registered at the same time, so that this isn't the order in which they were logged.
+    async fn setup(fixture: &Fixture) -> Vec<String> {
+        let Fixture {
+            server,
+            owner,
+            other,
+            course,
+            assignment,
+        } = fixture;
+
+        let university = server.university("Cal Poly Humboldt").await;
+        let major = server.major("Physics").await;
+
+        let followed = [
+            other.clone(),
+            server
+                .user("alexkim", ("Alex", "Kim"), university, major)
+                .await,
+            server
+                .user("samrivera", ("Sam", "Rivera"), university, major)
+                .await,
+        ];
+        let stranger = server
+            .user("alexlee", ("Alex", "Lee"), university, major)
+            .await;
+
+        for user in &followed {
+            server.follow(owner, user).await;
+        }
+
+        let mut expected = Vec::new();
+
+        // interleave the users' activity, with some logging more than others
+        for round in 0..4 {
+            for (i, user) in followed.iter().enumerate().take(round + 1) {
+                let data = if i % 2 == 0 {
+                    ActivityData::Planning
+                } else {
+                    ActivityData::Completed
+                };
+
+                let activity = server.activity(user, *course, *assignment, data).await;
+                let hour = 12 - expected.len() / 2;
+
+                server
+                    .db()
+                    .query("UPDATE $activity SET time = <datetime>$time")
+                    .bind(("activity", record_id("activity", activity)))
+                    .bind(("time", format!("2024-09-01T{hour:02}:00:00Z")))
+                    .await
+                    .unwrap()
+                    .check()
+                    .unwrap();
+
+                expected.push((hour, activity));
+            }
+
+            server
+                .activity(&stranger, *course, *assignment, ActivityData::Planning)
+                .await;
+        }
+
+        expected.sort();
+        expected.reverse();
+
+        expected
+            .into_iter()
+            .map(|(_, activity)| activity.to_string())
+            .collect()
+    }
+
+    async fn get(fixture: &Fixture, query: &str) -> Value {
+        let url = format!("/api/user/{}/feed?{query}", fixture.owner.id);
+        let response = fixture.server.client.get(url).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        body_json(response).await
+    }
+
+    fn ids(page: &Value) -> Vec<String> {
+        page["items"]
+            .as_array()
+            .expect("page has no items")
+            .iter()
+            .map(|item| item["id"].as_str().expect("item has no ID").to_owned())
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn lists_followed_users_newest_first() {
+        let fixture = Fixture::new().await;
+        let expected = setup(&fixture).await;
+        assert_eq!(expected.len(), 9);
+
+        let page = get(&fixture, "limit=200").await;
+        assert_eq!(ids(&page), expected);
+        assert!(page["next_cursor"].is_null());
+
+        for item in page["items"].as_array().unwrap() {
+            assert_ne!(item["user"]["username"], "alexlee");
+            assert_eq!(item["course"]["id"], fixture.course.to_string());
+            assert_eq!(item["course"]["code"], "MAT 4170");
+            assert_eq!(item["assignment"]["id"], fixture.assignment.to_string());
+            assert_eq!(item["assignment"]["name"], "HW 2");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn pages_across_followed_users() {
+        let fixture = Fixture::new().await;
+        let expected = setup(&fixture).await;
+
+        for limit in [1, 2, 4, 9] {
+            let mut found = Vec::new();
+            let mut page = get(&fixture, &format!("limit={limit}")).await;
+
+            loop {
+                let page_ids = ids(&page);
+                assert!(page_ids.len() <= limit);
+                found.extend(page_ids);
+
+                let Some(cursor) = page["next_cursor"].as_str() else {
+                    break;
+                };
+
+                page = get(&fixture, &format!("limit={limit}&cursor={cursor}")).await;
+            }
+
+            assert_eq!(found, expected, "{limit}");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn is_empty_without_followed_users() {
+        let fixture = Fixture::new().await;
+
+        let page = get(&fixture, "").await;
+        assert_eq!(ids(&page), Vec::<String>::new());
+        assert!(page["next_cursor"].is_null());
+    }
+}
+
+mod list_routes {
+    use super::*;
+
+    /// Follow `next_cursor` from the first page of the given list route to the last, returning all
+    /// of the items.
+    async fn all_pages(server: &TestServer, url: &str, limit: usize) -> Vec<String> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let mut page_url = format!("{url}?limit={limit}");
+            if let Some(cursor) = &cursor {
+                page_url += &format!("&cursor={cursor}");
+            }
+
+            let response = server.client.get(&page_url).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{page_url}");
+            let page = body_json(response).await;
+
+            let page_items = page["items"].as_array().expect("page has no items");
+            assert!(page_items.len() <= limit);
+            items.extend(page_items.iter().map(|id| id.as_str().unwrap().to_owned()));
+
+            match page["next_cursor"].as_str() {
+                Some(next) => cursor = Some(next.to_owned()),
+                None => break,
+            }
+        }
+
+        items
+    }
+
+    #[rocket::async_test]
+    async fn pages_through_items_in_order_of_id() {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let major = server.major("Mathematics").await;
+
+        let user = server
+            .user("jacobhenn", ("Jacob", "Henn"), university, major)
+            .await;
+
+        let mut others = Vec::new();
+        let mut courses = Vec::new();
+
+        for i in 0..5 {
+            let other = server
+                .user(&format!("user{i}"), ("Alex", "Kim"), university, major)
+                .await;
+            server.follow(&user, &other).await;
+            server.follow(&other, &user).await;
+            others.push(other.id.to_string());
+
+            let course = server
+                .course(university, &format!("MAT {i}"), "Algebra")
+                .await;
+            server.enroll(&user, course).await;
+            courses.push(course.to_string());
+        }
+
+        others.sort();
+        courses.sort();
+
+        let mut students = others.clone();
+        students.push(user.id.to_string());
+        students.sort();
+
+        let routes = [
+            (format!("/api/user/{}/following", user.id), &others),
+            (format!("/api/user/{}/followers", user.id), &others),
+            (format!("/api/user/{}/courses", user.id), &courses),
+            (format!("/api/uni/{university}/students"), &students),
+        ];
+
+        for (url, expected) in routes {
+            for limit in [1, 2, 5, 200] {
+                assert_eq!(
+                    &all_pages(&server, &url, limit).await,
+                    expected,
+                    "{url} {limit}"
+                );
+            }
+        }
+    }
+}
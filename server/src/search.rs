@@ -0,0 +1,1201 @@
+//! Full-text search of users, courses, universities, majors, and assignments.
+//!
+//! Records matching a search string are found through the full-text indexes defined in
+//! `surql/setup_tables.surql`, which score them with BM25, and are then re-ranked by how well they
+//! fuzzy-match the search string. Both scores are scaled onto 0 to 1 and averaged into a
+//! relevance. When several tables are searched at once, BM25 scores are scaled over all of their
+//! results together, so that relevance is comparable between kinds of results (see
+//! [`search_all`]).
+//!
+//! Searching ignores case and accents (e.g. "jose" finds "José" and vice versa), so results are
+//! fuzzy-matched by their folded names (see `fn::fold`) against the folded search string.
+//!
+//! Searching also tolerates typos (e.g. "lancsater" finds "Lancaster"). If the full-text search
+//! doesn't find enough results, records which share some trigrams with the search string are found
+//! through another index (see `fn::trigrams`), and those which the full-text search missed are
+//! kept if each word of the search string is within a few typos of some word of the record (see
+//! [`typo_similarity`]).
+//!
+//! When the user making a search is known, results related to them (e.g. classmates) are boosted
+//! so that they outrank similarly-named strangers; see [`SearchBoosts`].
+
+use crate::{
+    db::record_id,
+    structs::{Name, USId},
+};
+
+use std::{collections::BTreeSet, str::FromStr};
+
+use color_eyre::eyre::{self, Result, WrapErr};
+
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
+use rocket::futures::{future::try_join_all, FutureExt};
+
+use serde::de::DeserializeOwned;
+
+use surrealdb::{engine::any::Any, Surreal};
+
+use triple_accel::levenshtein::rdamerau;
+
+use ulid::Ulid;
+
+/// Maximum number of matching records which are ranked per table, so that very vague searches
+/// don't load entire tables into memory. The query picks the records with the best BM25 scores.
+const MAX_CANDIDATES: usize = 1000;
+
+/// Maximum number of records per table which are checked for being a match with typos. The query
+/// picks the records which share the most trigrams with the search string.
+const MAX_TYPO_CANDIDATES: usize = 200;
+
+/// Restrictions on which records a search finds, for narrowing down searches with many similar
+/// results (e.g. "CS majors graduating in 2026 at my university"). Each filter which is given must
+/// match; kinds of records to which a filter doesn't apply ignore it.
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    /// Only find users who attend, or courses offered at, this university.
+    pub university: Option<Ulid>,
+
+    /// Only find users with this major.
+    pub major: Option<Ulid>,
+
+    /// Only find users graduating in this year.
+    pub grad_year: Option<i32>,
+
+    /// Only find users who take this course.
+    pub course: Option<Ulid>,
+}
+
+/// A kind of record which can be searched for.
+pub trait Searchable: DeserializeOwned {
+    /// The query finding records which match `$search`. It should use the full-text indexes to
+    /// find them (e.g. `WHERE folded_name @1@ $search`), select their BM25 score (e.g.
+    /// `search::score(1) AS score`), and end with `ORDER BY score DESC LIMIT $candidates`.
+    ///
+    /// The [`Filters`] which apply to this kind of record are bound as `$university`, `$major`,
+    /// `$grad_year`, and `$course`, which are `NONE` if not given. They should be checked in the
+    /// `WHERE` clause of an outer query around the one using the index, since SurrealDB won't use
+    /// the index for a condition with other conditions `AND`ed onto it.
+    const QUERY: &'static str;
+
+    /// Like [`Self::QUERY`], but finding records which might match `$search` with typos, given the
+    /// trigrams of the search string as `$trigrams`. It should find them through the trigram index
+    /// (`WHERE trigrams CONTAINSANY $trigrams`), select the number of trigrams they share with the
+    /// search string as their score, and end with `ORDER BY score DESC LIMIT $typo_candidates`.
+    const TYPO_QUERY: &'static str;
+
+    fn id(&self) -> USId;
+
+    /// The score selected by [`Self::QUERY`] or [`Self::TYPO_QUERY`].
+    fn score(&self) -> f64;
+
+    /// The folded fields to fuzzy-match against the folded search string.
+    fn keys(&self) -> Vec<&str>;
+
+    /// The weights of the boosts which apply to this result when searched for by the given user.
+    fn boosts(&self, _searcher: &Searcher) -> Vec<f64> {
+        Vec::new()
+    }
+}
+
+/// How much to favor search results which are related to the user making the search. Each weight
+/// is from 0 to 1, and each boost which applies to a result closes that fraction of the gap between
+/// the result's relevance and 1. This way, relevance stays between 0 and 1, and boosts matter less
+/// the better a result already matches. When the searcher is known, relevance is halved before
+/// boosting, since otherwise a perfect match would leave no gap, and e.g. a classmate would only
+/// tie with a stranger of the same name.
+#[derive(clap::Args, Debug, Clone, Copy)]
+pub struct SearchBoosts {
+    /// How much to favor users at the searcher's university in search results.
+    #[arg(long, default_value_t = 0.2, value_parser = parse_weight)]
+    pub search_boost_same_university: f64,
+
+    /// How much to favor users who take a course that the searcher takes in search results.
+    #[arg(long, default_value_t = 0.3, value_parser = parse_weight)]
+    pub search_boost_shared_course: f64,
+
+    /// How much to favor users who the searcher follows, or who are followed by someone the
+    /// searcher follows, in search results.
+    #[arg(long, default_value_t = 0.3, value_parser = parse_weight)]
+    pub search_boost_followed: f64,
+
+    /// How much to favor courses at the searcher's university in search results.
+    #[arg(long, default_value_t = 0.3, value_parser = parse_weight)]
+    pub search_boost_course_at_university: f64,
+}
+
+/// Parse a weight of [`SearchBoosts`], which must be from 0 to 1.
+fn parse_weight(s: &str) -> std::result::Result<f64, String> {
+    let weight: f64 = s.parse().map_err(|err| format!("{err}"))?;
+
+    if !(0.0..=1.0).contains(&weight) {
+        return Err("must be from 0 to 1".to_owned());
+    }
+
+    Ok(weight)
+}
+
+/// What is known about the user making a search, for boosting results related to them.
+#[derive(Debug)]
+pub struct Searcher {
+    university: USId,
+
+    /// Courses that the searcher takes.
+    courses: BTreeSet<USId>,
+
+    /// Users who the searcher follows, or who are followed by someone the searcher follows, not
+    /// including the searcher.
+    followed: BTreeSet<USId>,
+
+    boosts: SearchBoosts,
+}
+
+/// Look up the user with the given ID for boosting the results of their searches. Returns `None` if
+/// there is no such user.
+pub async fn searcher(
+    db: &Surreal<Any>,
+    user: Ulid,
+    boosts: SearchBoosts,
+) -> Result<Option<Searcher>> {
+    #[derive(serde::Deserialize)]
+    struct Relations {
+        university: USId,
+        courses: BTreeSet<USId>,
+        followed: BTreeSet<USId>,
+    }
+
+    let relations: Option<Relations> = db
+        .query(
+            "SELECT
+                university,
+                ->takes_course->course AS courses,
+                array::complement(
+                    array::union(->follows->user, ->follows->user->follows->user),
+                    [id]
+                ) AS followed
+            FROM ONLY $user",
+        )
+        .bind(("user", record_id("user", user)))
+        .await
+        .wrap_err("failed to query searcher")?
+        .take(0)
+        .wrap_err("failed to query searcher")?;
+
+    Ok(relations.map(|relations| Searcher {
+        university: relations.university,
+        courses: relations.courses,
+        followed: relations.followed,
+        boosts,
+    }))
+}
+
+/// A search result along with how relevant it is to the search string, from 0 to 1.
+#[derive(serde::Serialize, Debug)]
+pub struct Scored<T> {
+    pub relevance: f64,
+
+    #[serde(flatten)]
+    pub result: T,
+}
+
+/// How a [`Candidate`] was found.
+#[derive(Debug, Clone, Copy)]
+enum Match {
+    /// Through the full-text indexes, with its BM25 score and its fuzzy-match score from 0 to 1.
+    FullText { bm25: f64, fuzzy: f64 },
+
+    /// Only through the trigram index, with its [`typo_similarity`].
+    Typos { similarity: f64 },
+}
+
+/// A record which matches a search string, before its relevance is computed (see [`rank`]).
+struct Candidate<T> {
+    result: T,
+    found: Match,
+}
+
+impl<T> Candidate<T> {
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Candidate<U> {
+        Candidate {
+            result: f(self.result),
+            found: self.found,
+        }
+    }
+}
+
+/// A user found by searching their username and name.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UserResult {
+    pub id: USId,
+    pub username: String,
+    pub name: Name,
+
+    #[serde(skip_serializing)]
+    folded_username: String,
+
+    #[serde(skip_serializing)]
+    folded_name: String,
+
+    #[serde(skip_serializing)]
+    score: f64,
+
+    #[serde(skip_serializing)]
+    university: USId,
+
+    /// Courses that the user takes.
+    #[serde(skip_serializing)]
+    courses: Vec<USId>,
+}
+
+impl Searchable for UserResult {
+    const QUERY: &'static str = "SELECT * FROM (
+            SELECT
+                id, username, name, folded_username, folded_name,
+                search::score(1) + search::score(2) AS score,
+                university, major, grad_year, ->takes_course->course AS courses
+            FROM user
+            WHERE folded_username @1@ $search OR folded_name @2@ $search
+        )
+        WHERE ($university == NONE OR university == $university)
+            AND ($major == NONE OR major == $major)
+            AND ($grad_year == NONE OR grad_year == $grad_year)
+            AND ($course == NONE OR $course IN courses)
+        ORDER BY score DESC
+        LIMIT $candidates";
+
+    const TYPO_QUERY: &'static str = "SELECT * FROM (
+            SELECT
+                id, username, name, folded_username, folded_name,
+                <float> array::len(array::intersect(trigrams, $trigrams)) AS score,
+                university, major, grad_year, ->takes_course->course AS courses
+            FROM user
+            WHERE trigrams CONTAINSANY $trigrams
+        )
+        WHERE ($university == NONE OR university == $university)
+            AND ($major == NONE OR major == $major)
+            AND ($grad_year == NONE OR grad_year == $grad_year)
+            AND ($course == NONE OR $course IN courses)
+        ORDER BY score DESC
+        LIMIT $typo_candidates";
+
+    fn id(&self) -> USId {
+        self.id
+    }
+
+    fn score(&self) -> f64 {
+        self.score
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        vec![&self.folded_username, &self.folded_name]
+    }
+
+    fn boosts(&self, searcher: &Searcher) -> Vec<f64> {
+        let boosts = &searcher.boosts;
+
+        [
+            (
+                self.university == searcher.university,
+                boosts.search_boost_same_university,
+            ),
+            (
+                self.courses
+                    .iter()
+                    .any(|course| searcher.courses.contains(course)),
+                boosts.search_boost_shared_course,
+            ),
+            (
+                searcher.followed.contains(&self.id),
+                boosts.search_boost_followed,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(applies, weight)| applies.then_some(weight))
+        .collect()
+    }
+}
+
+/// A course found by searching its name and code.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct CourseResult {
+    pub id: USId,
+    pub name: String,
+    pub code: String,
+
+    #[serde(skip_serializing)]
+    folded_name: String,
+
+    #[serde(skip_serializing)]
+    folded_code: String,
+
+    #[serde(skip_serializing)]
+    score: f64,
+
+    #[serde(skip_serializing)]
+    university: USId,
+}
+
+impl Searchable for CourseResult {
+    const QUERY: &'static str = "SELECT * FROM (
+            SELECT
+                id, name, code, folded_name, folded_code,
+                search::score(1) + search::score(2) AS score,
+                university
+            FROM course
+            WHERE folded_name @1@ $search OR folded_code @2@ $search
+        )
+        WHERE $university == NONE OR university == $university
+        ORDER BY score DESC
+        LIMIT $candidates";
+
+    const TYPO_QUERY: &'static str = "SELECT * FROM (
+            SELECT
+                id, name, code, folded_name, folded_code,
+                <float> array::len(array::intersect(trigrams, $trigrams)) AS score,
+                university
+            FROM course
+            WHERE trigrams CONTAINSANY $trigrams
+        )
+        WHERE $university == NONE OR university == $university
+        ORDER BY score DESC
+        LIMIT $typo_candidates";
+
+    fn id(&self) -> USId {
+        self.id
+    }
+
+    fn score(&self) -> f64 {
+        self.score
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        vec![&self.folded_name, &self.folded_code]
+    }
+
+    fn boosts(&self, searcher: &Searcher) -> Vec<f64> {
+        if self.university == searcher.university {
+            vec![searcher.boosts.search_boost_course_at_university]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// An assignment found by searching its name.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct AssignmentResult {
+    pub id: USId,
+    pub course: USId,
+    pub name: String,
+
+    #[serde(skip_serializing)]
+    folded_name: String,
+
+    #[serde(skip_serializing)]
+    score: f64,
+}
+
+impl Searchable for AssignmentResult {
+    const QUERY: &'static str =
+        "SELECT id, course, name, folded_name, search::score(1) AS score FROM assignment
+        WHERE folded_name @1@ $search
+        ORDER BY score DESC
+        LIMIT $candidates";
+
+    const TYPO_QUERY: &'static str = "SELECT
+            id, course, name, folded_name,
+            <float> array::len(array::intersect(trigrams, $trigrams)) AS score
+        FROM assignment
+        WHERE trigrams CONTAINSANY $trigrams
+        ORDER BY score DESC
+        LIMIT $typo_candidates";
+
+    fn id(&self) -> USId {
+        self.id
+    }
+
+    fn score(&self) -> f64 {
+        self.score
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        vec![&self.folded_name]
+    }
+}
+
+/// A university found by searching its name.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UniResult {
+    pub id: USId,
+    pub name: String,
+
+    #[serde(skip_serializing)]
+    folded_name: String,
+
+    #[serde(skip_serializing)]
+    score: f64,
+}
+
+impl Searchable for UniResult {
+    const QUERY: &'static str =
+        "SELECT id, name, folded_name, search::score(1) AS score FROM university
+        WHERE folded_name @1@ $search
+        ORDER BY score DESC
+        LIMIT $candidates";
+
+    const TYPO_QUERY: &'static str = "SELECT
+            id, name, folded_name,
+            <float> array::len(array::intersect(trigrams, $trigrams)) AS score
+        FROM university
+        WHERE trigrams CONTAINSANY $trigrams
+        ORDER BY score DESC
+        LIMIT $typo_candidates";
+
+    fn id(&self) -> USId {
+        self.id
+    }
+
+    fn score(&self) -> f64 {
+        self.score
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        vec![&self.folded_name]
+    }
+}
+
+/// A major found by searching its name.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct MajorResult {
+    pub id: USId,
+    pub name: String,
+
+    #[serde(skip_serializing)]
+    folded_name: String,
+
+    #[serde(skip_serializing)]
+    score: f64,
+}
+
+impl Searchable for MajorResult {
+    const QUERY: &'static str = "SELECT id, name, folded_name, search::score(1) AS score FROM major
+        WHERE folded_name @1@ $search
+        ORDER BY score DESC
+        LIMIT $candidates";
+
+    const TYPO_QUERY: &'static str = "SELECT
+            id, name, folded_name,
+            <float> array::len(array::intersect(trigrams, $trigrams)) AS score
+        FROM major
+        WHERE trigrams CONTAINSANY $trigrams
+        ORDER BY score DESC
+        LIMIT $typo_candidates";
+
+    fn id(&self) -> USId {
+        self.id
+    }
+
+    fn score(&self) -> f64 {
+        self.score
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        vec![&self.folded_name]
+    }
+}
+
+/// The function scaling scores linearly so that the lowest of the given scores becomes 0 and the
+/// highest becomes 1. If they are all the same, it scales every score to 1.
+fn normalizer(scores: &[f64]) -> impl Fn(f64) -> f64 {
+    let (min, max) = scores
+        .iter()
+        .copied()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), score| {
+            (min.min(score), max.max(score))
+        });
+
+    move |score| {
+        if max > min {
+            (score - min) / (max - min)
+        } else {
+            1.0
+        }
+    }
+}
+
+/// The number of typos allowed in a word of a search string: none in short words, since almost any
+/// short word is a typo or two away from a different short word, and at most two in long ones.
+fn max_typos(word: &str) -> u32 {
+    (word.len() / 4).min(2) as u32
+}
+
+/// The number of typos in the given word of a search string if it was meant to be the given word
+/// of a result, or the beginning of it (so that results can be found before the user has finished
+/// typing).
+fn typos(word: &str, key_word: &str) -> u32 {
+    let typos = rdamerau(word.as_bytes(), key_word.as_bytes());
+
+    match key_word.get(..word.len()) {
+        Some(prefix) => typos.min(rdamerau(word.as_bytes(), prefix.as_bytes())),
+        None => typos,
+    }
+}
+
+/// How similar a search string is to a result with the given keys if each of its words is meant
+/// to be some word of the keys, from 0 to 1 by the fraction of characters which are typos. Returns
+/// `None` if some word has more typos than [`max_typos`] allows. Damerau-Levenshtein distance is
+/// used so that swapping two letters (e.g. "lancsater") counts as a single typo.
+fn typo_similarity(search: &str, keys: &[&str]) -> Option<f64> {
+    let key_words: Vec<&str> = keys.iter().flat_map(|key| key.split_whitespace()).collect();
+
+    let mut total_typos = 0;
+    let mut total_len = 0;
+
+    for word in search.split_whitespace() {
+        let typos = key_words
+            .iter()
+            .map(|key_word| typos(word, key_word))
+            .min()?;
+
+        if typos > max_typos(word) {
+            return None;
+        }
+
+        total_typos += typos;
+        total_len += word.len();
+    }
+
+    if total_len == 0 {
+        return None;
+    }
+
+    Some(1.0 - total_typos as f64 / total_len as f64)
+}
+
+/// Sort results in decreasing order of relevance, and then by ID so that the order is stable.
+fn sort_by_relevance<T>(results: &mut [Scored<T>], get_id: impl Fn(&T) -> USId) {
+    results.sort_by(|a, b| {
+        b.relevance
+            .total_cmp(&a.relevance)
+            .then_with(|| get_id(&a.result).cmp(&get_id(&b.result)))
+    });
+}
+
+/// A stage of finding the records which match a search string.
+#[derive(Debug, Clone, Copy)]
+enum Stage<'a> {
+    /// Through the full-text indexes.
+    FullText,
+
+    /// Through the trigram index, skipping the given records which were already found through the
+    /// full-text indexes.
+    Typos { found: &'a BTreeSet<USId> },
+}
+
+/// Find records of the given kind which match the search string and filters in the given stage,
+/// in no particular order. Fuzzy-match scores are scaled relative to the score of the search string
+/// matched against itself, so that a result gets 1 if it is a perfect match regardless of what else
+/// was found.
+async fn candidates<T: Searchable>(
+    db: &Surreal<Any>,
+    search: &str,
+    filters: &Filters,
+    stage: Stage<'_>,
+) -> Result<Vec<Candidate<T>>> {
+    let query = match stage {
+        Stage::FullText => T::QUERY,
+        Stage::Typos { .. } => T::TYPO_QUERY,
+    };
+
+    let mut response = db
+        .query("RETURN fn::fold($search)")
+        .query("LET $trigrams = fn::trigrams($search)")
+        .query(query)
+        .bind(("search", search.to_owned()))
+        .bind(("candidates", MAX_CANDIDATES))
+        .bind(("typo_candidates", MAX_TYPO_CANDIDATES))
+        .bind((
+            "university",
+            filters.university.map(|id| record_id("university", id)),
+        ))
+        .bind(("major", filters.major.map(|id| record_id("major", id))))
+        .bind(("grad_year", filters.grad_year))
+        .bind(("course", filters.course.map(|id| record_id("course", id))))
+        .await
+        .wrap_err("failed to run search query")?;
+
+    let search: Option<String> = response.take(0).wrap_err("failed to fold search string")?;
+    let search = search.unwrap_or_default();
+
+    let results: Vec<T> = response.take(2).wrap_err("failed to get search results")?;
+
+    match stage {
+        Stage::FullText => {
+            let matcher = SkimMatcherV2::default();
+
+            let perfect_match = matcher.fuzzy_match(&search, &search).unwrap_or(1).max(1) as f64;
+
+            Ok(results
+                .into_iter()
+                .map(|result| {
+                    let fuzzy = result
+                        .keys()
+                        .into_iter()
+                        .filter_map(|key| matcher.fuzzy_match(key, &search))
+                        .max()
+                        .unwrap_or(0) as f64;
+
+                    Candidate {
+                        found: Match::FullText {
+                            bm25: result.score(),
+                            fuzzy: (fuzzy / perfect_match).clamp(0.0, 1.0),
+                        },
+                        result,
+                    }
+                })
+                .collect())
+        }
+        Stage::Typos { found } => Ok(results
+            .into_iter()
+            .filter(|result| !found.contains(&result.id()))
+            .filter_map(|result| {
+                let similarity = typo_similarity(&search, &result.keys())?;
+
+                Some(Candidate {
+                    result,
+                    found: Match::Typos { similarity },
+                })
+            })
+            .collect()),
+    }
+}
+
+/// Compute the relevance of each of the candidates found by a search, and sort them in decreasing
+/// order of it. If the searcher is given, results related to them are boosted (see
+/// [`SearchBoosts`]).
+///
+/// BM25 scores depend on the contents of the table, so they are only comparable between results
+/// of the same search, and are scaled so that the best of the given candidates gets 1. The
+/// relevance of a result found through the full-text indexes is the average of that and its
+/// fuzzy-match score. Results which are only found with typos get half of their similarity as
+/// their relevance, so that they rank below most results found without typos.
+fn rank<T>(
+    candidates: Vec<Candidate<T>>,
+    searcher: Option<&Searcher>,
+    get_boosts: impl Fn(&T, &Searcher) -> Vec<f64>,
+    get_id: impl Fn(&T) -> USId,
+) -> Vec<Scored<T>> {
+    let bm25_scores: Vec<f64> = candidates
+        .iter()
+        .filter_map(|candidate| match candidate.found {
+            Match::FullText { bm25, .. } => Some(bm25),
+            Match::Typos { .. } => None,
+        })
+        .collect();
+
+    let normalize_bm25 = normalizer(&bm25_scores);
+
+    let mut results: Vec<Scored<T>> = candidates
+        .into_iter()
+        .map(|Candidate { result, found }| {
+            let relevance = match found {
+                Match::FullText { bm25, fuzzy } => (normalize_bm25(bm25) + fuzzy) / 2.0,
+                Match::Typos { similarity } => similarity / 2.0,
+            };
+
+            let relevance = match searcher {
+                Some(searcher) => get_boosts(&result, searcher)
+                    .into_iter()
+                    .fold(relevance / 2.0, |relevance, weight| {
+                        relevance + weight * (1.0 - relevance)
+                    }),
+                None => relevance,
+            };
+
+            Scored { relevance, result }
+        })
+        .collect();
+
+    sort_by_relevance(&mut results, get_id);
+
+    results
+}
+
+/// Find records of the given kind which match the search string and filters, sorted in decreasing
+/// order of relevance (see [`rank`]). If the searcher is given, results related to them are
+/// boosted (see [`SearchBoosts`]).
+///
+/// Records with typos are only looked for if the ones found through the full-text indexes aren't
+/// `enough` (e.g. to fill a page). Adding them doesn't change the relevance of the others, so they
+/// just fill in below the others; only a result with typos which is boosted above some without
+/// them may be left out of a page that the others filled.
+pub async fn search<T: Searchable>(
+    db: &Surreal<Any>,
+    search: &str,
+    filters: &Filters,
+    searcher: Option<&Searcher>,
+    enough: impl Fn(&[Scored<T>]) -> bool,
+) -> Result<Vec<Scored<T>>> {
+    let full_text = candidates::<T>(db, search, filters, Stage::FullText).await?;
+    let found: BTreeSet<USId> = full_text
+        .iter()
+        .map(|candidate| candidate.result.id())
+        .collect();
+
+    let mut results = rank(full_text, searcher, T::boosts, T::id);
+
+    if !enough(&results) {
+        let typos = candidates::<T>(db, search, filters, Stage::Typos { found: &found }).await?;
+
+        results.extend(rank(typos, searcher, T::boosts, T::id));
+        sort_by_relevance(&mut results, T::id);
+    }
+
+    Ok(results)
+}
+
+/// A kind of record searched by [`search_all`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchKind {
+    User,
+    Course,
+    Uni,
+    Major,
+    Assignment,
+}
+
+impl SearchKind {
+    pub const ALL: [SearchKind; 5] = [
+        SearchKind::User,
+        SearchKind::Course,
+        SearchKind::Uni,
+        SearchKind::Major,
+        SearchKind::Assignment,
+    ];
+}
+
+/// Parses from the same names as [`AnyResult`] is tagged with, e.g. "user" or "uni".
+impl FromStr for SearchKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "user" => Ok(SearchKind::User),
+            "course" => Ok(SearchKind::Course),
+            "uni" => Ok(SearchKind::Uni),
+            "major" => Ok(SearchKind::Major),
+            "assignment" => Ok(SearchKind::Assignment),
+            _ => eyre::bail!("unknown search kind {s:?}"),
+        }
+    }
+}
+
+/// A result of any kind, tagged with its kind in a `type` field.
+#[derive(serde::Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnyResult {
+    User(UserResult),
+    Course(CourseResult),
+    Uni(UniResult),
+    Major(MajorResult),
+    Assignment(AssignmentResult),
+}
+
+impl AnyResult {
+    pub fn id(&self) -> USId {
+        match self {
+            AnyResult::User(result) => result.id,
+            AnyResult::Course(result) => result.id,
+            AnyResult::Uni(result) => result.id,
+            AnyResult::Major(result) => result.id,
+            AnyResult::Assignment(result) => result.id,
+        }
+    }
+
+    fn boosts(&self, searcher: &Searcher) -> Vec<f64> {
+        match self {
+            AnyResult::User(result) => result.boosts(searcher),
+            AnyResult::Course(result) => result.boosts(searcher),
+            AnyResult::Uni(result) => result.boosts(searcher),
+            AnyResult::Major(result) => result.boosts(searcher),
+            AnyResult::Assignment(result) => result.boosts(searcher),
+        }
+    }
+}
+
+/// Helper function for finding candidates of one kind of record and tagging them with their kind.
+async fn tagged_candidates<T: Searchable>(
+    db: &Surreal<Any>,
+    search_str: &str,
+    filters: &Filters,
+    stage: Stage<'_>,
+    tag: fn(T) -> AnyResult,
+) -> Result<Vec<Candidate<AnyResult>>> {
+    let candidates = candidates::<T>(db, search_str, filters, stage).await?;
+
+    Ok(candidates
+        .into_iter()
+        .map(|candidate| candidate.map(tag))
+        .collect())
+}
+
+/// Search records of each of the given kinds concurrently, and rank the results together into one
+/// list sorted in decreasing order of relevance (see [`rank`]). BM25 scores are scaled over all of
+/// the results rather than per kind, so that the best match of a kind which only matched weakly
+/// doesn't get the same score as the best match overall. Filters apply to the kinds they concern
+/// and are ignored by the rest (see [`Filters`]). Like [`search`], records with typos are only
+/// looked for if the others aren't `enough`.
+pub async fn search_all(
+    db: &Surreal<Any>,
+    search_str: &str,
+    filters: &Filters,
+    searcher: Option<&Searcher>,
+    kinds: &[SearchKind],
+    enough: impl Fn(&[Scored<AnyResult>]) -> bool,
+) -> Result<Vec<Scored<AnyResult>>> {
+    let find_candidates = |stage| {
+        let searches = kinds.iter().map(move |kind| match kind {
+            SearchKind::User => {
+                tagged_candidates(db, search_str, filters, stage, AnyResult::User).boxed()
+            }
+            SearchKind::Course => {
+                tagged_candidates(db, search_str, filters, stage, AnyResult::Course).boxed()
+            }
+            SearchKind::Uni => {
+                tagged_candidates(db, search_str, filters, stage, AnyResult::Uni).boxed()
+            }
+            SearchKind::Major => {
+                tagged_candidates(db, search_str, filters, stage, AnyResult::Major).boxed()
+            }
+            SearchKind::Assignment => {
+                tagged_candidates(db, search_str, filters, stage, AnyResult::Assignment).boxed()
+            }
+        });
+
+        try_join_all(searches)
+    };
+
+    let full_text: Vec<Candidate<AnyResult>> = find_candidates(Stage::FullText)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+    let found: BTreeSet<USId> = full_text
+        .iter()
+        .map(|candidate| candidate.result.id())
+        .collect();
+
+    let mut results = rank(full_text, searcher, AnyResult::boosts, AnyResult::id);
+
+    if !enough(&results) {
+        let typos: Vec<Candidate<AnyResult>> = find_candidates(Stage::Typos { found: &found })
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+
+        results.extend(rank(typos, searcher, AnyResult::boosts, AnyResult::id));
+        sort_by_relevance(&mut results, AnyResult::id);
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TestServer;
+
+    /// The names of the results of a search, in order.
+    fn names(results: &[Scored<MajorResult>]) -> Vec<&str> {
+        results
+            .iter()
+            .map(|scored| scored.result.name.as_str())
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn ranks_by_bm25_rather_than_name() {
+        let server = TestServer::new().await;
+
+        for name in ["Applied Data Science", "Data Science"] {
+            server.major(name).await;
+        }
+
+        // BM25 gives terms in most of a table's records a negative weight, which real tables of
+        // majors are too big for
+        for name in ["Mathematics", "History", "Chemistry", "Physics", "English"] {
+            server.major(name).await;
+        }
+
+        let results = search::<MajorResult>(
+            server.db(),
+            "data science",
+            &Filters::default(),
+            None,
+            |_| false,
+        )
+        .await
+        .unwrap();
+
+        // the shorter name matches just as many words, so it scores higher despite sorting later
+        assert_eq!(names(&results), ["Data Science", "Applied Data Science"]);
+        assert!(results[0].result.score > results[1].result.score);
+    }
+
+    #[rocket::async_test]
+    async fn ranks_weak_match_of_one_kind_below_strong_match_of_another() {
+        let server = TestServer::new().await;
+
+        // created first so that it would win a tie
+        let weak = server
+            .university("Data Science Institute of Technology and Applied Research")
+            .await;
+        let strong = server.major("Data Science").await;
+
+        for name in [
+            "Cal Poly Pomona",
+            "University of Oxford",
+            "Harvey Mudd College",
+        ] {
+            server.university(name).await;
+        }
+
+        for name in ["Mathematics", "History", "Chemistry", "Physics", "English"] {
+            server.major(name).await;
+        }
+
+        let results = search_all(
+            server.db(),
+            "data science",
+            &Filters::default(),
+            None,
+            &SearchKind::ALL,
+            |_| false,
+        )
+        .await
+        .unwrap();
+
+        let ids: Vec<Ulid> = results
+            .iter()
+            .map(|scored| scored.result.id().ulid())
+            .collect();
+        assert_eq!(ids, [strong, weak]);
+        assert!(results[1].relevance < results[0].relevance);
+    }
+
+    #[test]
+    fn allows_more_typos_in_longer_words() {
+        assert_eq!(max_typos("cs"), 0);
+        assert_eq!(max_typos("cal"), 0);
+        assert_eq!(max_typos("jacob"), 1);
+        assert_eq!(max_typos("lancaster"), 2);
+        assert_eq!(max_typos("interdisciplinary"), 2);
+    }
+
+    #[test]
+    fn counts_each_kind_of_typo_once() {
+        // transposition
+        assert_eq!(typos("jcaob", "jacob"), 1);
+        assert_eq!(typos("lancsater", "lancaster"), 1);
+        // omission
+        assert_eq!(typos("lancster", "lancaster"), 1);
+        // insertion
+        assert_eq!(typos("lancasfter", "lancaster"), 1);
+        // substitution
+        assert_eq!(typos("lancaxter", "lancaster"), 1);
+        // an unfinished word is a prefix, not a typo
+        assert_eq!(typos("lanc", "lancaster"), 0);
+        assert_eq!(typos("lnac", "lancaster"), 1);
+    }
+
+    #[test]
+    fn scores_similarity_by_fraction_of_typos() {
+        let keys = ["jacobhenn", "jacob henn"];
+
+        assert_eq!(typo_similarity("jacob henn", &keys), Some(1.0));
+        assert_eq!(typo_similarity("jcaob", &keys), Some(0.8));
+        assert_eq!(typo_similarity("jcaob hnen", &keys), Some(1.0 - 2.0 / 9.0));
+        assert_eq!(typo_similarity("", &keys), None);
+    }
+
+    #[test]
+    fn rejects_words_with_too_many_typos() {
+        let keys = ["lancaster university"];
+
+        assert!(typo_similarity("lnacsater", &keys).is_some());
+        assert_eq!(typo_similarity("lnacsatre", &keys), None);
+
+        // short words must match exactly
+        let keys = ["cal poly pomona"];
+
+        assert!(typo_similarity("cal", &keys).is_some());
+        assert_eq!(typo_similarity("cla", &keys), None);
+        assert_eq!(typo_similarity("jcoab", &["jacob"]), None);
+    }
+
+    #[rocket::async_test]
+    async fn finds_typos_through_trigram_index() {
+        let server = TestServer::new().await;
+
+        let lancaster = server.university("Lancaster University").await;
+        server.university("Cal Poly Pomona").await;
+
+        let results =
+            search::<UniResult>(server.db(), "lancsater", &Filters::default(), None, |_| {
+                false
+            })
+            .await
+            .unwrap();
+
+        let ids: Vec<Ulid> = results
+            .iter()
+            .map(|scored| scored.result.id.ulid())
+            .collect();
+        assert_eq!(ids, [lancaster]);
+
+        // found only with typos, which gets at most half of the relevance of a perfect match
+        assert!(results[0].relevance <= 0.5);
+
+        let plan: Vec<serde_json::Value> = server
+            .db()
+            .query("LET $trigrams = fn::trigrams('lancsater')")
+            .query(format!("{} EXPLAIN", UniResult::TYPO_QUERY))
+            .bind(("typo_candidates", MAX_TYPO_CANDIDATES))
+            .await
+            .unwrap()
+            .take(1)
+            .unwrap();
+
+        assert_eq!(plan[0]["operation"], "Iterate Index");
+        assert_eq!(plan[0]["detail"]["plan"]["index"], "search_trigrams");
+    }
+
+    /// The IDs of the records of the given kind which match the search string through the full-text
+    /// indexes, without looking for typos.
+    async fn full_text_matches<T: Searchable>(server: &TestServer, search: &str) -> Vec<Ulid> {
+        candidates::<T>(server.db(), search, &Filters::default(), Stage::FullText)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|candidate| candidate.result.id().ulid())
+            .collect()
+    }
+
+    #[rocket::async_test]
+    async fn folds_accents_and_case_in_full_text_search() {
+        let server = TestServer::new().await;
+
+        let wien = server.university("Universität Wien").await;
+        let pomona = server.university("Cal Poly Pomona").await;
+        let major = server.major("Physics").await;
+
+        let jose = server
+            .user("jgarcia", ("José", "García"), wien, major)
+            .await
+            .id;
+        let anders = server
+            .user("anders", ("Anders", "Ångström"), wien, major)
+            .await
+            .id;
+        server
+            .user("jacobhenn", ("Jacob", "Henn"), pomona, major)
+            .await;
+
+        for (search_str, expected) in [
+            ("jose", jose),
+            ("José", jose),
+            ("JOSE GARCIA", jose),
+            ("angstrom", anders),
+            ("Ångström", anders),
+            ("ÅNGSTRÖM", anders),
+        ] {
+            assert_eq!(
+                full_text_matches::<UserResult>(&server, search_str).await,
+                [expected],
+                "{search_str}"
+            );
+        }
+
+        for search_str in ["universitat wien", "Universität", "UNIVERSITÄT WIEN"] {
+            assert_eq!(
+                full_text_matches::<UniResult>(&server, search_str).await,
+                [wien],
+                "{search_str}"
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn looks_for_typos_only_if_not_enough_results_without_them() {
+        let server = TestServer::new().await;
+
+        let lancaster = server.university("Lancaster University").await;
+        let bible_college = server.university("Lancaster Bible College").await;
+        let typo = server.university("Lancsater Polytechnic").await;
+        server.university("Cal Poly Pomona").await;
+
+        let ids = |results: Vec<Scored<UniResult>>| -> Vec<Ulid> {
+            results
+                .into_iter()
+                .map(|scored| scored.result.id.ulid())
+                .collect()
+        };
+
+        let filters = Filters::default();
+
+        let search_for = |count: usize| {
+            search::<UniResult>(server.db(), "lancaster", &filters, None, move |results| {
+                results.len() >= count
+            })
+        };
+
+        let mut results = ids(search_for(2).await.unwrap());
+        results.sort();
+        assert_eq!(results, [lancaster, bible_college]);
+
+        let results = ids(search_for(3).await.unwrap());
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[2], typo);
+
+        let results = search_all(
+            server.db(),
+            "lancaster",
+            &Filters::default(),
+            None,
+            &SearchKind::ALL,
+            |results| results.len() >= 2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(results.len(), 2);
+    }
+
+    /// Typos are counted in bytes, which only works since both sides are folded to ASCII first.
+    #[rocket::async_test]
+    async fn finds_accented_names_with_typos() {
+        let server = TestServer::new().await;
+
+        let montreal = server.university("Université de Montréal").await;
+
+        for search_str in [
+            "université de montraél",
+            "universite de montrael",
+            "UNIVERSITE MONTRAEL",
+        ] {
+            let results =
+                search::<UniResult>(server.db(), search_str, &Filters::default(), None, |_| {
+                    false
+                })
+                .await
+                .unwrap();
+
+            let ids: Vec<Ulid> = results
+                .iter()
+                .map(|scored| scored.result.id.ulid())
+                .collect();
+            assert_eq!(ids, [montreal], "{search_str}");
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Recomputation of the data on each user that is derived from their activity.
+//!
+//! Normally, a user's [`Stats`] and assignment status sets are maintained incrementally by the
+//! `update_users` event in `surql/setup_tables.surql`. If they ever drift (e.g. because of data
+//! inserted before the event existed or by hand), [`repair`] rebuilds all of them from scratch by
+//! replaying the `activity` table in time order. Activity registered at the same time is replayed
+//! in order of ID, like the event does.
+
+use crate::{
+    db::record_id,
+    structs::{ActivityData, Stats, USId},
+};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::TimeDelta;
+
+use color_eyre::eyre::{Result, WrapErr};
+
+use serde_with::{serde_as, DurationSeconds};
+
+use surrealdb::{engine::any::Any, RecordId, Surreal};
+
+use tracing::{info, instrument, warn};
+
+/// All of the data on a user which is derived from their activity.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ActivityState {
+    pub stats: Stats,
+    pub assignments_planning: BTreeSet<USId>,
+    pub assignments_in_progress: BTreeSet<USId>,
+    pub assignments_completed: BTreeSet<USId>,
+}
+
+impl Default for ActivityState {
+    fn default() -> Self {
+        Self {
+            stats: Stats {
+                assignments_completed: 0,
+                duration_worked: TimeDelta::zero(),
+            },
+            assignments_planning: BTreeSet::new(),
+            assignments_in_progress: BTreeSet::new(),
+            assignments_completed: BTreeSet::new(),
+        }
+    }
+}
+
+impl ActivityState {
+    /// Apply a single activity on the given assignment to this state. This must mirror the
+    /// `update_users` event and `fn::update_assignment_status`.
+    fn apply(&mut self, assignment: USId, data: &ActivityData) {
+        match data {
+            ActivityData::Planning => {
+                self.assignments_planning.insert(assignment);
+            }
+            ActivityData::WorkedOn { duration } => {
+                self.assignments_planning.remove(&assignment);
+                self.assignments_in_progress.insert(assignment);
+                self.stats.duration_worked += *duration;
+            }
+            ActivityData::Completed => {
+                self.assignments_in_progress.remove(&assignment);
+                self.assignments_completed.insert(assignment);
+                self.stats.assignments_completed += 1;
+            }
+        }
+    }
+}
+
+/// A user whose stored activity-derived data differed from the result of replaying their
+/// activity, and has therefore been overwritten.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Discrepancy {
+    pub user: USId,
+    pub stored: ActivityState,
+    pub replayed: ActivityState,
+}
+
+/// Recompute every user's [`ActivityState`] by replaying the `activity` table in time order,
+/// overwrite the stored state of every user for which it differs, and return a list of those
+/// users.
+///
+/// The replay and the overwrites are not done in one transaction, so activity registered, edited,
+/// or deleted during a repair may be overwritten with stale data. Repairs must therefore be run
+/// while no activity is being changed, e.g. with the server stopped.
+#[instrument(skip(db))]
+pub async fn repair(db: &Surreal<Any>) -> Result<Vec<Discrepancy>> {
+    #[derive(serde::Deserialize)]
+    struct Activity {
+        user: USId,
+        assignment: USId,
+        data: ActivityData,
+    }
+
+    /// The stored values are read leniently (missing values are treated as empty) since they are
+    /// exactly what might be broken.
+    #[serde_as]
+    #[derive(serde::Deserialize)]
+    struct StoredUser {
+        id: USId,
+        assignments_completed_count: usize,
+        #[serde_as(as = "DurationSeconds<i64>")]
+        secs_worked: TimeDelta,
+        assignments_planning: BTreeSet<USId>,
+        assignments_in_progress: BTreeSet<USId>,
+        assignments_completed: BTreeSet<USId>,
+    }
+
+    info!("replaying activity");
+
+    let activity: Vec<Activity> = db
+        .query("SELECT id, time, user, assignment, data FROM activity ORDER BY time ASC, id ASC")
+        .await
+        .wrap_err("failed to query activity")?
+        .take(0)
+        .wrap_err("failed to deserialize activity")?;
+
+    let mut replayed: BTreeMap<USId, ActivityState> = BTreeMap::new();
+
+    for Activity {
+        user,
+        assignment,
+        data,
+    } in activity
+    {
+        replayed.entry(user).or_default().apply(assignment, &data);
+    }
+
+    let users: Vec<StoredUser> = db
+        .query(
+            "SELECT
+                id,
+                stats.assignments_completed ?? 0 AS assignments_completed_count,
+                stats.secs_worked ?? 0 AS secs_worked,
+                assignments_planning ?? [] AS assignments_planning,
+                assignments_in_progress ?? [] AS assignments_in_progress,
+                assignments_completed ?? [] AS assignments_completed
+            FROM user",
+        )
+        .await
+        .wrap_err("failed to query users")?
+        .take(0)
+        .wrap_err("failed to deserialize users")?;
+
+    let mut discrepancies = Vec::new();
+
+    for user in users {
+        let stored = ActivityState {
+            stats: Stats {
+                assignments_completed: user.assignments_completed_count,
+                duration_worked: user.secs_worked,
+            },
+            assignments_planning: user.assignments_planning,
+            assignments_in_progress: user.assignments_in_progress,
+            assignments_completed: user.assignments_completed,
+        };
+
+        let replayed = replayed.remove(&user.id).unwrap_or_default();
+
+        if stored == replayed {
+            continue;
+        }
+
+        warn!("activity-derived data of user {} has drifted", user.id);
+
+        let assignments = |set: &BTreeSet<USId>| -> Vec<RecordId> {
+            set.iter()
+                .map(|id| record_id("assignment", id.ulid()))
+                .collect()
+        };
+
+        db.query(
+            "UPDATE $user SET
+                stats = {
+                    assignments_completed: $assignments_completed,
+                    secs_worked: $secs_worked
+                },
+                assignments_planning = $planning,
+                assignments_in_progress = $in_progress,
+                assignments_completed = $completed",
+        )
+        .bind(("user", record_id("user", user.id.ulid())))
+        .bind((
+            "assignments_completed",
+            replayed.stats.assignments_completed,
+        ))
+        .bind(("secs_worked", replayed.stats.duration_worked.num_seconds()))
+        .bind(("planning", assignments(&replayed.assignments_planning)))
+        .bind((
+            "in_progress",
+            assignments(&replayed.assignments_in_progress),
+        ))
+        .bind(("completed", assignments(&replayed.assignments_completed)))
+        .await
+        .wrap_err_with(|| format!("failed to overwrite data of user {}", user.id))?
+        .check()
+        .wrap_err_with(|| format!("failed to overwrite data of user {}", user.id))?;
+
+        discrepancies.push(Discrepancy {
+            user: user.id,
+            stored,
+            replayed,
+        });
+    }
+
+    info!("repaired {} users", discrepancies.len());
+
+    Ok(discrepancies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{TestServer, TestUser};
+
+    use ulid::Ulid;
+
+    /// A server with a user taking a course, and an assignment in that course.
+    async fn setup() -> (TestServer, TestUser, Ulid, Ulid) {
+        let server = TestServer::new().await;
+
+        let university = server.university("Cal Poly Pomona").await;
+        let major = server.major("Mathematics").await;
+        let user = server
+            .user("jacobhenn", ("Jacob", "Henn"), university, major)
+            .await;
+
+        let course = server
+            .course(university, "MAT 4170", "Abstract Algebra I")
+            .await;
+        let assignment = server.assignment(course, "HW 2").await;
+
+        server.enroll(&user, course).await;
+
+        (server, user, course, assignment)
+    }
+
+    /// The IDs of the assignments in each of the given user's status sets, as stored.
+    async fn stored_statuses(server: &TestServer, user: &TestUser) -> [Vec<Ulid>; 3] {
+        let state: Option<ActivityState> = server
+            .db()
+            .query(
+                "SELECT stats, assignments_planning, assignments_in_progress, assignments_completed
+                FROM ONLY $user",
+            )
+            .bind(("user", record_id("user", user.id)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+
+        let state = state.unwrap();
+
+        [
+            state.assignments_planning,
+            state.assignments_in_progress,
+            state.assignments_completed,
+        ]
+        .map(|ids| ids.into_iter().map(USId::ulid).collect())
+    }
+
+    #[rocket::async_test]
+    async fn repairs_drifted_users() {
+        let (server, user, course, assignment) = setup().await;
+
+        let other = server
+            .user("choobipanda", ("Amy", "Nguyen"), Ulid::new(), Ulid::new())
+            .await;
+        server.enroll(&other, course).await;
+
+        let worked_on = ActivityData::WorkedOn {
+            duration: TimeDelta::seconds(1500),
+        };
+        server.activity(&user, course, assignment, worked_on).await;
+        server
+            .activity(&user, course, assignment, ActivityData::Completed)
+            .await;
+        server
+            .activity(&other, course, assignment, ActivityData::Planning)
+            .await;
+
+        assert_eq!(repair(server.db()).await.unwrap().len(), 0);
+
+        server
+            .db()
+            .query(
+                "UPDATE $user SET
+                    stats.secs_worked = 60,
+                    assignments_in_progress = [],
+                    assignments_completed = []",
+            )
+            .bind(("user", record_id("user", user.id)))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let discrepancies = repair(server.db()).await.unwrap();
+        assert_eq!(discrepancies.len(), 1);
+
+        let Discrepancy {
+            user: id,
+            stored,
+            replayed,
+        } = &discrepancies[0];
+        assert_eq!(id.ulid(), user.id);
+
+        assert_eq!(stored.stats.duration_worked, TimeDelta::seconds(60));
+        assert_eq!(stored.stats.assignments_completed, 1);
+        assert!(stored.assignments_completed.is_empty());
+
+        assert_eq!(replayed.stats.duration_worked, TimeDelta::seconds(1500));
+        assert_eq!(replayed.stats.assignments_completed, 1);
+        let replayed_completed: Vec<_> = replayed
+            .assignments_completed
+            .iter()
+            .map(|id| id.ulid())
+            .collect();
+        assert_eq!(replayed_completed, [assignment]);
+
+        let secs_worked: Option<i64> = server
+            .db()
+            .query("SELECT VALUE stats.secs_worked FROM ONLY $user")
+            .bind(("user", record_id("user", user.id)))
+            .await
+            .unwrap()
+            .take(0)
+            .unwrap();
+        assert_eq!(secs_worked, Some(1500));
+        assert_eq!(
+            stored_statuses(&server, &user).await,
+            [vec![], vec![], vec![assignment]]
+        );
+        assert_eq!(
+            stored_statuses(&server, &other).await,
+            [vec![assignment], vec![], vec![]]
+        );
+
+        assert_eq!(repair(server.db()).await.unwrap().len(), 0);
+    }
+
+    #[rocket::async_test]
+    async fn replays_simultaneous_activity_in_order_of_id() {
+        let (server, user, course, assignment) = setup().await;
+
+        let worked_on = ActivityData::WorkedOn {
+            duration: TimeDelta::seconds(1500),
+        };
+        let first = server.activity(&user, course, assignment, worked_on).await;
+        let second = server
+            .activity(&user, course, assignment, ActivityData::Planning)
+            .await;
+
+        // replaying the work and then the planning leaves the assignment in both sets, whereas the
+        // opposite order would leave it only in progress
+        for activity in [second, first] {
+            server
+                .db()
+                .query("UPDATE $activity SET time = d'2024-09-02T10:00:00Z'")
+                .bind(("activity", record_id("activity", activity)))
+                .await
+                .unwrap()
+                .check()
+                .unwrap();
+        }
+
+        assert_eq!(
+            stored_statuses(&server, &user).await,
+            [vec![assignment], vec![assignment], vec![]]
+        );
+
+        assert_eq!(repair(server.db()).await.unwrap().len(), 0);
+    }
+}
@@ -7,7 +7,7 @@ use serde_with::{serde_as, DurationSeconds};
 use ulid::Ulid;
 
 /// See [`USId`]
-#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum IdInner {
     String(Ulid),
 }
@@ -23,11 +23,29 @@ enum IdInner {
 /// make this helper struct with an asymmetric implementation of `Serialize` and `Deserialize` that
 /// "forgets" all of the awkward structure of SurrealDB IDs when sending API responses, but still
 /// correctly deserializes them from the results of database queries.
-#[derive(serde::Deserialize, Debug, Clone, Copy)]
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct USId {
     id: IdInner,
 }
 
+impl USId {
+    /// The ULID part of this ID, e.g. for passing to [`crate::db::record_id`].
+    pub fn ulid(self) -> Ulid {
+        let IdInner::String(id) = self.id;
+
+        id
+    }
+}
+
+/// Displays as the bare ULID.
+impl std::fmt::Display for USId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let IdInner::String(id) = self.id;
+
+        write!(f, "{id}")
+    }
+}
+
 impl serde::Serialize for USId {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -55,14 +73,20 @@ pub struct User {
     pub university: USId,
     pub major: USId,
     pub grad_year: i32,
+
+    /// ID of the user's profile picture, which is served at "/media/<id>".
+    pub profile_picture: Option<Ulid>,
 }
 
-/// A course; for now, these are independent of university since many courses are ubiquitous
-/// and offered at every university, and it would be convenient for users to be able to search for
-/// other users based on shared university and shared courses independently.
+/// A course offered at a particular university. Courses are input by users, so the same course
+/// may be input more than once; see [`crate::courses`] for how that is mitigated.
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct Course {
     pub id: USId,
+    pub university: USId,
+
+    /// Normalized course code, e.g. "MAT 4170".
+    pub code: String,
     pub name: String,
 }
 
@@ -90,7 +114,7 @@ pub enum ActivityData {
 }
 
 #[serde_as]
-#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct Stats {
     /// The total number of assignments that the user has completed.
     pub assignments_completed: usize,
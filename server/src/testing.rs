@@ -0,0 +1,265 @@
+//! Helpers for testing the server against a temporary in-memory database (see [`db::in_memory`]).
+
+use crate::{
+    auth,
+    db::{self, record_id},
+    media_store::LocalStore,
+    search::SearchBoosts,
+    structs::ActivityData,
+    State,
+};
+
+use std::{io::Cursor, sync::Arc};
+
+use clap::Parser;
+
+use image::{ImageFormat, RgbImage};
+
+use rocket::{config::LogLevel, data::ByteUnit, http::Header, local::asynchronous::Client};
+
+use surrealdb::{engine::any::Any, method::Query, Surreal};
+
+use tempfile::TempDir;
+
+use ulid::Ulid;
+
+/// Parses the default [`SearchBoosts`], so that tests use the same weights as the server.
+#[derive(clap::Parser)]
+struct BoostArgs {
+    #[command(flatten)]
+    search_boosts: SearchBoosts,
+}
+
+/// A solid-colored PNG image of the given size.
+pub fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    RgbImage::from_pixel(width, height, [0x3b, 0x1f, 0x6b].into())
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .expect("failed to encode PNG");
+
+    bytes
+}
+
+/// A server with an empty in-memory database, storing media in a temporary directory which is
+/// deleted when the server is dropped.
+pub struct TestServer {
+    pub client: Client,
+
+    _media_dir: TempDir,
+}
+
+/// A user created by [`TestServer::user`], along with a session token to authenticate as them.
+#[derive(Debug, Clone)]
+pub struct TestUser {
+    pub id: Ulid,
+    pub token: String,
+}
+
+impl TestUser {
+    /// The `Authorization` header authenticating a request as this user.
+    pub fn auth(&self) -> Header<'static> {
+        Header::new("Authorization", format!("Bearer {}", self.token))
+    }
+}
+
+impl TestServer {
+    /// Start a server with the same configuration as the server's defaults.
+    pub async fn new() -> Self {
+        Self::with(|_| ()).await
+    }
+
+    /// Start a server, first changing its configuration with the given function.
+    pub async fn with(configure: impl FnOnce(&mut State<Any>)) -> Self {
+        let media_dir = TempDir::new().expect("failed to create media directory");
+
+        let mut state = State {
+            db: db::in_memory().await.expect("failed to create database"),
+            media_store: Arc::new(LocalStore::new(media_dir.path().to_owned())),
+            media_quota: ByteUnit::Mebibyte(64),
+            media_uploads_per_hour: 20,
+            restrict_enrollment_to_university: false,
+            search_boosts: BoostArgs::parse_from(["test"]).search_boosts,
+        };
+
+        configure(&mut state);
+
+        // the test harness captures logging, so it's only shown for failed tests
+        let _ = tracing_subscriber::fmt().with_test_writer().try_init();
+
+        let config = rocket::Config {
+            log_level: LogLevel::Off,
+            ..rocket::Config::debug_default()
+        };
+
+        let client = Client::tracked(crate::rocket(state).configure(config))
+            .await
+            .expect("failed to launch server");
+
+        Self {
+            client,
+            _media_dir: media_dir,
+        }
+    }
+
+    pub fn state(&self) -> &State<Any> {
+        self.client.rocket().state().expect("server has no state")
+    }
+
+    pub fn db(&self) -> &Surreal<Any> {
+        &self.state().db
+    }
+
+    /// Run a query which creates a single record and returns its ID with `record::id`, returning
+    /// that ID.
+    async fn create(&self, query: Query<'_, Any>) -> Ulid {
+        let id: Option<String> = query
+            .await
+            .expect("failed to create record")
+            .take(0)
+            .expect("failed to create record");
+
+        id.expect("no record was created")
+            .parse()
+            .expect("record ID is not a ULID")
+    }
+
+    pub async fn university(&self, name: &str) -> Ulid {
+        self.create(
+            self.db()
+                .query("CREATE ONLY university:ulid() SET name = $name RETURN VALUE record::id(id)")
+                .bind(("name", name.to_owned())),
+        )
+        .await
+    }
+
+    pub async fn major(&self, name: &str) -> Ulid {
+        self.create(
+            self.db()
+                .query("CREATE ONLY major:ulid() SET name = $name RETURN VALUE record::id(id)")
+                .bind(("name", name.to_owned())),
+        )
+        .await
+    }
+
+    pub async fn course(&self, university: Ulid, code: &str, name: &str) -> Ulid {
+        self.create(
+            self.db()
+                .query(
+                    "CREATE ONLY course:ulid() SET
+                        university = $university,
+                        code = $code,
+                        name = $name
+                    RETURN VALUE record::id(id)",
+                )
+                .bind(("university", record_id("university", university)))
+                .bind(("code", code.to_owned()))
+                .bind(("name", name.to_owned())),
+        )
+        .await
+    }
+
+    pub async fn assignment(&self, course: Ulid, name: &str) -> Ulid {
+        self.create(
+            self.db()
+                .query(
+                    "CREATE ONLY assignment:ulid() SET course = $course, name = $name
+                    RETURN VALUE record::id(id)",
+                )
+                .bind(("course", record_id("course", course)))
+                .bind(("name", name.to_owned())),
+        )
+        .await
+    }
+
+    /// Create a user with the given username and name (first and last) who has no password, and
+    /// log them in.
+    pub async fn user(
+        &self,
+        username: &str,
+        (first, last): (&str, &str),
+        university: Ulid,
+        major: Ulid,
+    ) -> TestUser {
+        let id = self
+            .create(
+                self.db()
+                    .query(
+                        "CREATE ONLY user:ulid() SET
+                            name.first = $first,
+                            name.last = $last,
+                            username = $username,
+                            university = $university,
+                            major = $major,
+                            grad_year = 2026,
+                            assignments_planning = [],
+                            assignments_in_progress = [],
+                            assignments_completed = [],
+                            stats.assignments_completed = 0,
+                            stats.secs_worked = 0
+                        RETURN VALUE record::id(id)",
+                    )
+                    .bind(("first", first.to_owned()))
+                    .bind(("last", last.to_owned()))
+                    .bind(("username", username.to_owned()))
+                    .bind(("university", record_id("university", university)))
+                    .bind(("major", record_id("major", major))),
+            )
+            .await;
+
+        let token = auth::create_session(self.db(), id)
+            .await
+            .expect("failed to create session");
+
+        TestUser { id, token }
+    }
+
+    pub async fn enroll(&self, user: &TestUser, course: Ulid) {
+        self.db()
+            .query("RELATE $user->takes_course->$course")
+            .bind(("user", record_id("user", user.id)))
+            .bind(("course", record_id("course", course)))
+            .await
+            .expect("failed to enroll user")
+            .check()
+            .expect("failed to enroll user");
+    }
+
+    pub async fn follow(&self, user: &TestUser, target: &TestUser) {
+        self.db()
+            .query("RELATE $user->follows->$target")
+            .bind(("user", record_id("user", user.id)))
+            .bind(("target", record_id("user", target.id)))
+            .await
+            .expect("failed to follow user")
+            .check()
+            .expect("failed to follow user");
+    }
+
+    /// Log an activity as the given user directly in the database, returning its ID.
+    pub async fn activity(
+        &self,
+        user: &TestUser,
+        course: Ulid,
+        assignment: Ulid,
+        data: ActivityData,
+    ) -> Ulid {
+        self.create(
+            self.db()
+                .query(
+                    "CREATE ONLY activity:ulid() SET
+                        user = $user,
+                        time = time::now(),
+                        course = $course,
+                        assignment = $assignment,
+                        data = $data
+                    RETURN VALUE record::id(id)",
+                )
+                .bind(("user", record_id("user", user.id)))
+                .bind(("course", record_id("course", course)))
+                .bind(("assignment", record_id("assignment", assignment)))
+                .bind(("data", data)),
+        )
+        .await
+    }
+}